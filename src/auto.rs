@@ -0,0 +1,308 @@
+//! Heuristics telling automatic brightness changes, made by ambient light daemons,
+//! apart from the ones made by the user
+
+use crate::{change::Change, config::Config, device::DeviceClass};
+use log::{debug, info};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs, thread,
+    time::{Duration, Instant},
+};
+use zbus::{
+    blocking::{Connection, MessageIterator},
+    MatchRule, MessageType,
+};
+
+/// Process names are truncated to this length in `/proc/<pid>/comm`
+const COMM_LEN: usize = 15;
+
+/// What to do with a change, as judged by [`AutoFilter::check`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// Made by the user, to be delivered
+    User,
+    /// May be the start of a ramp, held back until [`AutoFilter::poll`] releases it
+    Held,
+    /// Looks automatic, for the given reason
+    Automatic(String),
+}
+
+pub struct AutoFilter {
+    /// Number of consecutive small steps making a ramp, 0 disables ramp detection
+    ramp_steps: usize,
+    /// Largest step in percent still considered part of a ramp
+    ramp_max_step: f64,
+    ramp_max_gap: Duration,
+    processes: Vec<String>,
+    signal_window: Duration,
+    last_signal: Option<Instant>,
+    /// Latest values per device, starting with the last known one before the changes
+    history: HashMap<(DeviceClass, String), VecDeque<(Instant, f64)>>,
+    /// Changes that may start a ramp, along with when they were received
+    held: HashMap<(DeviceClass, String), (Change, Instant)>,
+}
+
+impl AutoFilter {
+    pub fn new(conf: &Config) -> Self {
+        let mut filter = AutoFilter {
+            ramp_steps: 0,
+            ramp_max_step: 0.,
+            ramp_max_gap: Duration::ZERO,
+            processes: Vec::new(),
+            signal_window: Duration::ZERO,
+            last_signal: None,
+            history: HashMap::new(),
+            held: HashMap::new(),
+        };
+        filter.configure(conf);
+        filter
+    }
+
+    pub fn configure(&mut self, conf: &Config) {
+        self.ramp_steps = conf.ramp_steps;
+        self.ramp_max_step = conf.ramp_max_step;
+        self.ramp_max_gap = Duration::from_millis(conf.ramp_max_gap);
+        self.processes = conf.inhibit_process.clone();
+        self.signal_window = Duration::from_millis(conf.signal_window);
+    }
+
+    /// Records the current value of a device, so its first change is judged too
+    pub fn seed(&mut self, change: &Change, now: Instant) {
+        self.history.insert(
+            (change.class, change.device.clone()),
+            [(now, change.ratio())].into(),
+        );
+    }
+
+    pub fn signal_received(&mut self, at: Instant) {
+        self.last_signal = Some(at);
+    }
+
+    /// Tells whether a change looks automatic. Small steps are held back while they
+    /// could still turn into a ramp
+    pub fn check(&mut self, change: &Change, now: Instant) -> Verdict {
+        let key = (change.class, change.device.clone());
+        let run = self.ramp_run(change, now);
+        if self.ramp_steps > 0 && run >= self.ramp_steps {
+            self.held.remove(&key);
+            return Verdict::Automatic(format!("{} is ramping smoothly", change.device));
+        }
+        let reason = match self.last_signal {
+            Some(at) if now.duration_since(at) <= self.signal_window => {
+                Some("an ambient light signal was just received".to_owned())
+            }
+            _ => self
+                .running_process()
+                .map(|name| format!("{name} is running")),
+        };
+        if let Some(reason) = reason {
+            self.held.remove(&key);
+            return Verdict::Automatic(reason);
+        }
+        if run > 0 {
+            self.held.insert(key, (change.clone(), now));
+            return Verdict::Held;
+        }
+        self.held.remove(&key);
+        Verdict::User
+    }
+
+    /// Takes the held changes that weren't followed by another step in time, so
+    /// they didn't start a ramp
+    pub fn poll(&mut self, now: Instant) -> Vec<Change> {
+        let gap = self.ramp_max_gap;
+        let mut due = Vec::new();
+        self.held.retain(|_, (change, at)| {
+            if now.duration_since(*at) >= gap {
+                due.push(change.clone());
+                return false;
+            }
+            true
+        });
+        due
+    }
+
+    /// When `poll` should be called next
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.held
+            .values()
+            .map(|(_, at)| *at + self.ramp_max_gap)
+            .min()
+    }
+
+    /// Records the change and counts the last steps of the device that were all
+    /// small, in the same direction and close in time, 0 if the change isn't small
+    fn ramp_run(&mut self, change: &Change, now: Instant) -> usize {
+        if self.ramp_steps == 0 {
+            return 0;
+        }
+        let history = self
+            .history
+            .entry((change.class, change.device.clone()))
+            .or_default();
+        history.push_back((now, change.ratio()));
+        while history.len() > self.ramp_steps + 1 {
+            history.pop_front();
+        }
+        let entries: Vec<_> = history.iter().collect();
+        let mut run = 0;
+        let mut direction = 0.;
+        for i in (1..entries.len()).rev() {
+            let ((t0, r0), (t1, r1)) = (entries[i - 1], entries[i]);
+            let delta = (r1 - r0) * 100.;
+            if delta == 0. || delta.abs() > self.ramp_max_step {
+                break;
+            }
+            if run > 0 && delta.signum() != direction {
+                break;
+            }
+            direction = delta.signum();
+            run += 1;
+            // The step before counts only if it came shortly before this one
+            if t1.duration_since(*t0) > self.ramp_max_gap {
+                break;
+            }
+        }
+        run
+    }
+
+    fn running_process(&self) -> Option<&str> {
+        if self.processes.is_empty() {
+            return None;
+        }
+        let running: Vec<String> = fs::read_dir("/proc")
+            .ok()?
+            .filter_map(|e| e.ok())
+            .filter_map(|e| fs::read_to_string(e.path().join("comm")).ok())
+            .map(|comm| comm.trim_end().to_owned())
+            .collect();
+        self.processes
+            .iter()
+            .find(|name| {
+                let name: String = name.chars().take(COMM_LEN).collect();
+                running.contains(&name)
+            })
+            .map(String::as_str)
+    }
+}
+
+/// Listens for signals of the given D-Bus interfaces on both the system and session
+/// bus, calling `received` for each one until it returns false
+pub fn listen_signals<F>(interfaces: &[String], received: F)
+where
+    F: Fn() -> bool + Clone + Send + 'static,
+{
+    for interface in interfaces {
+        for (bus, connect) in [
+            (
+                "system",
+                Connection::system as fn() -> zbus::Result<Connection>,
+            ),
+            ("session", Connection::session),
+        ] {
+            let iter = connect().and_then(|conn| {
+                let rule = MatchRule::builder()
+                    .msg_type(MessageType::Signal)
+                    .interface(interface.as_str())?
+                    .build();
+                MessageIterator::for_match_rule(rule, &conn, Some(16))
+            });
+            let iter = match iter {
+                Ok(iter) => iter,
+                Err(err) => {
+                    debug!("not listening for {interface} on the {bus} bus: {err}");
+                    continue;
+                }
+            };
+            info!("listening for {interface} signals on the {bus} bus");
+            let received = received.clone();
+            let spawned = thread::Builder::new()
+                .name(format!("{bus} bus listener"))
+                .spawn(move || {
+                    for msg in iter.flatten() {
+                        debug!("ambient light signal received: {:?}", msg.member());
+                        if !received() {
+                            break;
+                        }
+                    }
+                });
+            if let Err(err) = spawned {
+                debug!("failed to spawn {bus} bus listener: {err}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debounce::{Debouncer, Edge};
+
+    /// Feeds `(millisecond, raw)` changes through the filter and a leading edge
+    /// debouncer the way the daemon does, ticking every 10 ms, and returns what
+    /// was delivered
+    fn deliver(filter: &mut AutoFilter, changes: &[(u64, u64)]) -> Vec<u64> {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(
+            Duration::from_millis(100),
+            Duration::from_secs(1),
+            Edge::Leading,
+        );
+        filter.seed(&Change::panel(50), start);
+        let mut delivered = Vec::new();
+        let end = changes.last().map_or(0, |(ms, _)| *ms) + 1000;
+        for tick in (0..=end).step_by(10) {
+            let now = start + Duration::from_millis(tick);
+            for (_, raw) in changes.iter().filter(|(ms, _)| *ms == tick) {
+                let c = Change::panel(*raw);
+                if filter.check(&c, now) == Verdict::User {
+                    delivered.extend(debouncer.push((), c, now));
+                }
+            }
+            for c in filter.poll(now) {
+                delivered.extend(debouncer.push((), c, now));
+            }
+            delivered.extend(debouncer.poll(now));
+        }
+        delivered.into_iter().map(|c| c.raw).collect()
+    }
+
+    fn ramp_filter() -> AutoFilter {
+        AutoFilter::new(&Config {
+            ramp_steps: 3,
+            ..Config::default()
+        })
+    }
+
+    #[test]
+    fn ramp_is_never_delivered() {
+        let ramp: Vec<_> = (0..10).map(|i| (i * 120, 51 + i)).collect();
+        assert_eq!(deliver(&mut ramp_filter(), &ramp), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn single_small_step_is_delivered_after_the_gap() {
+        assert_eq!(deliver(&mut ramp_filter(), &[(0, 51)]), [51]);
+    }
+
+    #[test]
+    fn large_step_is_delivered_right_away() {
+        let mut filter = ramp_filter();
+        let c = Change::panel(60);
+        filter.seed(&Change::panel(50), Instant::now());
+        assert_eq!(filter.check(&c, Instant::now()), Verdict::User);
+    }
+
+    #[test]
+    fn steps_too_far_apart_are_not_a_ramp() {
+        let steps: Vec<_> = (0..4).map(|i| (i * 500, 51 + i)).collect();
+        assert_eq!(deliver(&mut ramp_filter(), &steps), [51, 52, 53, 54]);
+    }
+
+    #[test]
+    fn disabled_ramp_detection_delivers_everything() {
+        let mut filter = AutoFilter::new(&Config::default());
+        let ramp: Vec<_> = (0..3).map(|i| (i * 120, 51 + i)).collect();
+        assert_eq!(deliver(&mut filter, &ramp), [51, 52, 53]);
+    }
+}
@@ -0,0 +1,143 @@
+//! Streaming output for status bars: the current state first, then a line per change
+
+use crate::{
+    config::Config, device::DeviceClass, event::BrightnessEvent, sink::Sink, template::Template,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt,
+    io::{self, Write},
+    str::FromStr,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BarFormat {
+    /// JSON objects for a waybar custom module with `return-type` set to `json`
+    Waybar,
+    /// i3bar protocol, as read by i3bar and swaybar
+    I3bar,
+    /// Plain text lines, as read by polybar and i3blocks
+    Plain,
+}
+
+impl FromStr for BarFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "waybar" => Ok(BarFormat::Waybar),
+            "i3bar" => Ok(BarFormat::I3bar),
+            "plain" => Ok(BarFormat::Plain),
+            _ => Err(format!(
+                "unknown bar output '{s}', expected waybar, i3bar or plain"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for BarFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            BarFormat::Waybar => "waybar",
+            BarFormat::I3bar => "i3bar",
+            BarFormat::Plain => "plain",
+        };
+        f.write_str(s)
+    }
+}
+
+pub struct BarSink {
+    format: BarFormat,
+    text: Template,
+    /// Latest event per device, as i3bar status lines hold a block for each of them
+    latest: BTreeMap<(DeviceClass, String), BrightnessEvent>,
+}
+
+impl BarSink {
+    pub fn new(format: BarFormat, conf: &Config) -> Result<Self, String> {
+        Ok(BarSink {
+            format,
+            text: Template::parse(&conf.bar_text)?,
+            latest: BTreeMap::new(),
+        })
+    }
+
+    fn line(&self, event: &BrightnessEvent) -> String {
+        let text = self.text.render(event);
+        let tooltip = format!("{}: {}/{}", event.device, event.raw, event.max);
+        match self.format {
+            BarFormat::Waybar => json!({
+                "text": text,
+                "percentage": event.percent,
+                "tooltip": tooltip,
+                "class": event.class.to_string(),
+            })
+            .to_string(),
+            BarFormat::I3bar => self.status_line(),
+            BarFormat::Plain => text,
+        }
+    }
+
+    /// Each line is a whole status line, i3bar reads them as elements of an endless array
+    fn status_line(&self) -> String {
+        let blocks: Vec<_> = self
+            .latest
+            .values()
+            .map(|event| {
+                json!({
+                    "full_text": self.text.render(event),
+                    "name": "blight-notify",
+                    "instance": event.device,
+                })
+            })
+            .collect();
+        format!("{},", json!(blocks))
+    }
+
+    fn record(&mut self, event: &BrightnessEvent) {
+        self.latest
+            .insert((event.class, event.device.clone()), event.clone());
+    }
+}
+
+impl Sink for BarSink {
+    fn init(&mut self, current: &[BrightnessEvent]) -> Result<(), Box<dyn Error>> {
+        let mut out = io::stdout().lock();
+        if self.format == BarFormat::I3bar {
+            writeln!(out, "{}", json!({ "version": 1 }))?;
+            writeln!(out, "[")?;
+        }
+        for event in current {
+            self.record(event);
+        }
+        // Waybar and plain lines only show one device, the first one until another changes
+        if let Some(first) = current.first() {
+            writeln!(out, "{}", self.line(first))?;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, event: &BrightnessEvent) -> Result<(), Box<dyn Error>> {
+        self.record(event);
+        writeln!(io::stdout(), "{}", self.line(event))?;
+        Ok(())
+    }
+
+    fn wants_all_changes(&self) -> bool {
+        true
+    }
+
+    fn validate(&self, conf: &Config) -> Result<(), Box<dyn Error>> {
+        Template::parse(&conf.bar_text)?;
+        Ok(())
+    }
+
+    fn configure(&mut self, conf: &Config) -> Result<(), Box<dyn Error>> {
+        self.text = Template::parse(&conf.bar_text)?;
+        Ok(())
+    }
+}
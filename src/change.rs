@@ -0,0 +1,75 @@
+use crate::device::DeviceClass;
+use std::{fmt, path::PathBuf, time::SystemTime};
+
+/// Brightness of a device as read by the watcher after a change
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub class: DeviceClass,
+    pub device: String,
+    /// Device directory in sysfs
+    pub path: PathBuf,
+    pub raw: u64,
+    pub max: u64,
+    /// When the value was read
+    pub timestamp: SystemTime,
+}
+
+impl Change {
+    pub fn ratio(&self) -> f64 {
+        if self.max == 0 {
+            return 0.;
+        }
+        self.raw as f64 / self.max as f64
+    }
+}
+
+#[cfg(test)]
+impl Change {
+    /// Change of a `panel` backlight with a maximum of 100
+    pub(crate) fn panel(raw: u64) -> Self {
+        Change {
+            class: DeviceClass::Backlight,
+            device: "panel".into(),
+            path: "/sys/class/backlight/panel".into(),
+            raw,
+            max: 100,
+            timestamp: SystemTime::now(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Up,
+    Down,
+    Unchanged,
+}
+
+impl Direction {
+    pub fn between(previous: Option<f64>, current: f64) -> Self {
+        match previous {
+            Some(p) if current > p => Direction::Up,
+            Some(p) if current < p => Direction::Down,
+            _ => Direction::Unchanged,
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Direction::Up => "▲",
+            Direction::Down => "▼",
+            Direction::Unchanged => "",
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Direction::Up => "up",
+            Direction::Down => "down",
+            Direction::Unchanged => "unchanged",
+        };
+        f.write_str(s)
+    }
+}
@@ -0,0 +1,207 @@
+//! One-shot subcommands, run instead of the daemon
+
+use argh::FromArgs;
+use blight_notify::{
+    config::Config,
+    device::{self, DeviceClass},
+};
+use log::error;
+use serde_json::json;
+use std::{error::Error, path::PathBuf};
+
+#[derive(FromArgs, Debug)]
+#[argh(subcommand)]
+pub enum Command {
+    Status(StatusArgs),
+    Get(GetArgs),
+    List(ListArgs),
+}
+
+#[derive(FromArgs, Debug)]
+#[argh(
+    subcommand,
+    name = "status",
+    description = "print the current brightness of the watched devices and exit"
+)]
+pub struct StatusArgs {
+    #[argh(switch, description = "print JSON instead of a table")]
+    json: bool,
+}
+
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "get", description = "same as status")]
+pub struct GetArgs {
+    #[argh(switch, description = "print JSON instead of a table")]
+    json: bool,
+}
+
+#[derive(FromArgs, Debug)]
+#[argh(
+    subcommand,
+    name = "list",
+    description = "list backlight and led devices with their attributes and whether they would be watched"
+)]
+pub struct ListArgs {
+    #[argh(switch, description = "print JSON instead of a table")]
+    json: bool,
+}
+
+impl Command {
+    pub fn run(&self, conf: &Config) -> Result<(), Box<dyn Error>> {
+        match self {
+            Command::Status(StatusArgs { json }) | Command::Get(GetArgs { json }) => {
+                status(conf, *json)
+            }
+            Command::List(ListArgs { json }) => list(conf, *json),
+        }
+    }
+}
+
+/// Reads the devices the daemon would watch, with percentages mapped as in notifications.
+/// Devices that can't be read are reported and skipped, failing only if none could be
+fn status(conf: &Config, json: bool) -> Result<(), Box<dyn Error>> {
+    let found = device::discover(&conf.sysfs_root, conf.classes())?;
+    let devices = conf.device_filter()?.select(found);
+    let mapping = conf.percent_mapping();
+    let reads: Vec<_> = devices.iter().map(|d| (d, d.read(conf.actual))).collect();
+    let failed = reads.iter().filter(|(_, read)| read.is_err()).count();
+    if json {
+        let rows: Vec<_> = reads
+            .iter()
+            .map(|(d, read)| match read {
+                Ok(c) => json!({
+                    "device": c.device,
+                    "class": c.class.to_string(),
+                    "path": c.path,
+                    "raw": c.raw,
+                    "max": c.max,
+                    "percent": mapping.percent(c),
+                }),
+                Err(err) => json!({
+                    "device": d.name,
+                    "class": d.class.to_string(),
+                    "path": d.path,
+                    "error": err.to_string(),
+                }),
+            })
+            .collect();
+        println!("{}", json!(rows));
+    } else {
+        let mut rows: Vec<Vec<String>> = Vec::new();
+        for (d, read) in &reads {
+            match read {
+                Ok(c) => rows.push(vec![
+                    c.device.clone(),
+                    c.class.to_string(),
+                    c.raw.to_string(),
+                    c.max.to_string(),
+                    mapping.percent(c).to_string(),
+                ]),
+                Err(err) => error!("failed to read {}: {err}", d.name),
+            }
+        }
+        print_table(&["DEVICE", "CLASS", "RAW", "MAX", "PERCENT"], &rows);
+    }
+    if failed > 0 && failed == reads.len() {
+        return Err("none of the devices could be read".into());
+    }
+    Ok(())
+}
+
+/// Lists every backlight and led, including the ones the filters or the keyboard
+/// option leave out
+fn list(conf: &Config, json: bool) -> Result<(), Box<dyn Error>> {
+    let watched: Vec<PathBuf> = conf
+        .device_filter()?
+        .select(device::discover(&conf.sysfs_root, conf.classes())?)
+        .into_iter()
+        .map(|d| d.path)
+        .collect();
+    let mut devices = Vec::new();
+    for class in [DeviceClass::Backlight, DeviceClass::Keyboard] {
+        devices.extend(device::scan_class(&conf.sysfs_root, class)?);
+    }
+    let entries: Vec<_> = devices
+        .iter()
+        .map(|d| (d, d.info(), watched.contains(&d.path)))
+        .collect();
+    if json {
+        let rows: Vec<_> = entries
+            .iter()
+            .map(|(d, info, watched)| {
+                json!({
+                    "name": d.name,
+                    "class": d.class.dir(),
+                    "type": (d.class == DeviceClass::Backlight).then(|| d.kind.to_string()),
+                    "max_brightness": info.max,
+                    "brightness": info.brightness,
+                    "bl_power": info.bl_power,
+                    "path": info.sys_path,
+                    "driver": info.driver,
+                    "connector": info.connector,
+                    "watched": watched,
+                })
+            })
+            .collect();
+        println!("{}", json!(rows));
+        return Ok(());
+    }
+    let cell = |value: Option<String>| value.unwrap_or_else(|| "-".into());
+    let rows: Vec<Vec<String>> = entries
+        .iter()
+        .map(|(d, info, watched)| {
+            vec![
+                d.name.clone(),
+                d.class.dir().to_owned(),
+                cell((d.class == DeviceClass::Backlight).then(|| d.kind.to_string())),
+                cell(info.max.map(|v| v.to_string())),
+                cell(info.brightness.map(|v| v.to_string())),
+                cell(info.bl_power.map(|v| v.to_string())),
+                cell(info.driver.clone()),
+                cell(info.connector.clone()),
+                if *watched { "yes" } else { "no" }.to_owned(),
+                cell(info.sys_path.as_ref().map(|p| p.display().to_string())),
+            ]
+        })
+        .collect();
+    print_table(
+        &[
+            "NAME",
+            "CLASS",
+            "TYPE",
+            "MAX",
+            "CURRENT",
+            "BL_POWER",
+            "DRIVER",
+            "CONNECTOR",
+            "WATCHED",
+            "PATH",
+        ],
+        &rows,
+    );
+    Ok(())
+}
+
+/// Prints left aligned columns, each as wide as its widest cell
+fn print_table(header: &[&str], rows: &[Vec<String>]) {
+    let widths: Vec<usize> = header
+        .iter()
+        .enumerate()
+        .map(|(i, h)| {
+            rows.iter()
+                .map(|r| r[i].chars().count())
+                .chain([h.len()])
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let header: Vec<String> = header.iter().map(|h| h.to_string()).collect();
+    for row in [&header].into_iter().chain(rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+}
@@ -0,0 +1,348 @@
+use crate::{
+    bar::BarFormat,
+    debounce::Edge,
+    device::{DeviceClass, DeviceFilter},
+    error::BlightError,
+    icon::IconTable,
+    scale::{PercentMapping, Rounding, Scale},
+    threshold::MinDelta,
+};
+use glob::{Pattern, PatternError};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+pub const SYSFS_ROOT_ENV: &str = "BLIGHT_NOTIFY_SYSFS_ROOT";
+
+/// Effective daemon configuration, loaded from the config file and overridden by
+/// command line options
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub title: String,
+    pub message: String,
+    pub icon: Option<String>,
+    pub level_icons: bool,
+    pub icon_levels: Option<IconTable>,
+    pub icon_up: Option<String>,
+    pub icon_down: Option<String>,
+    pub timeout: u32,
+    pub urgency: Urgency,
+    pub pollrate: f32,
+    pub debounce: u64,
+    pub max_latency: u64,
+    pub debounce_edge: Edge,
+    pub scale: Scale,
+    pub exponent: f64,
+    pub rounding: Rounding,
+    pub min_delta: Option<MinDelta>,
+    pub percent_changes: bool,
+    pub ramp_steps: usize,
+    pub ramp_max_step: f64,
+    pub ramp_max_gap: u64,
+    pub inhibit_process: Vec<String>,
+    pub inhibit_signal: Vec<String>,
+    pub signal_window: u64,
+    pub backend: Backend,
+    pub rescan: f32,
+    pub sysfs_root: PathBuf,
+    pub device: Vec<String>,
+    pub exclude_device: Vec<String>,
+    pub auto_select: bool,
+    pub actual: bool,
+    pub keyboard: bool,
+    pub kbd_title: String,
+    pub kbd_message: String,
+    pub kbd_icon: Option<String>,
+    pub no_progress: bool,
+    /// Outputs to send changes to, notifications unless a bar output replaces them
+    pub output: Option<Vec<Output>>,
+    pub stdout_format: String,
+    pub exec: Vec<String>,
+    pub fifo: Option<PathBuf>,
+    pub fifo_format: String,
+    pub bar_output: Option<BarFormat>,
+    pub bar_text: String,
+    pub watch_config: bool,
+    /// Notification overrides keyed by device name
+    pub profile: BTreeMap<String, Profile>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            title: "Blight".into(),
+            message: "Brightness adjusted:".into(),
+            icon: None,
+            level_icons: false,
+            icon_levels: None,
+            icon_up: None,
+            icon_down: None,
+            timeout: 1000,
+            urgency: Urgency::Low,
+            pollrate: 0.5,
+            debounce: 100,
+            max_latency: 250,
+            debounce_edge: Edge::Both,
+            scale: Scale::Linear,
+            exponent: 4.,
+            rounding: Rounding::Truncate,
+            min_delta: None,
+            percent_changes: false,
+            ramp_steps: 0,
+            ramp_max_step: 2.,
+            ramp_max_gap: 200,
+            inhibit_process: Vec::new(),
+            inhibit_signal: Vec::new(),
+            signal_window: 1000,
+            backend: Backend::Auto,
+            rescan: 5.,
+            sysfs_root: PathBuf::from("/sys"),
+            device: Vec::new(),
+            exclude_device: Vec::new(),
+            auto_select: false,
+            actual: false,
+            keyboard: false,
+            kbd_title: "Blight".into(),
+            kbd_message: "Keyboard backlight adjusted:".into(),
+            kbd_icon: None,
+            no_progress: false,
+            output: None,
+            stdout_format: "{device}: {percent}%".into(),
+            exec: Vec::new(),
+            fifo: None,
+            fifo_format: "{percent}".into(),
+            bar_output: None,
+            bar_text: "{percent}%".into(),
+            watch_config: false,
+            profile: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config file at the given path, or the one at the default location
+    /// if it exists, falling back to the default configuration
+    pub fn load(path: Option<&Path>) -> Result<Self, BlightError> {
+        let (path, required) = match path {
+            Some(p) => (p.to_path_buf(), true),
+            None => match default_path() {
+                Some(p) => (p, false),
+                None => return Ok(Config::default()),
+            },
+        };
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if !required && err.kind() == io::ErrorKind::NotFound => {
+                return Ok(Config::default())
+            }
+            Err(source) => return Err(BlightError::Read { path, source }),
+        };
+        debug!("loading config from {}", path.display());
+        let conf: Config = toml::from_str(&content).map_err(|err| BlightError::Config {
+            path: path.clone(),
+            message: err.to_string(),
+        })?;
+        conf.validate()
+            .map_err(|message| BlightError::Config { path, message })?;
+        Ok(conf)
+    }
+
+    /// Checks the values the types alone don't constrain
+    pub fn validate(&self) -> Result<(), String> {
+        seconds("rescan", self.rescan)?;
+        if seconds("pollrate", self.pollrate)?.is_zero() {
+            return Err("pollrate must be more than 0 seconds".into());
+        }
+        if self.bar_output.is_some()
+            && self
+                .outputs()
+                .iter()
+                .any(|o| matches!(o, Output::Stdout | Output::Json))
+        {
+            return Err("stdout and json outputs would mix with the bar output on stdout".into());
+        }
+        Ok(())
+    }
+
+    pub fn outputs(&self) -> &[Output] {
+        match &self.output {
+            Some(output) => output,
+            None if self.bar_output.is_some() => &[],
+            None => &[Output::Notify],
+        }
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).expect("config is always serializable")
+    }
+
+    pub fn device_filter(&self) -> Result<DeviceFilter, PatternError> {
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|p| Pattern::new(p))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(DeviceFilter {
+            include: compile(&self.device)?,
+            exclude: compile(&self.exclude_device)?,
+            auto_select: self.auto_select,
+        })
+    }
+
+    pub fn classes(&self) -> &'static [DeviceClass] {
+        if self.keyboard {
+            &[DeviceClass::Backlight, DeviceClass::Keyboard]
+        } else {
+            &[DeviceClass::Backlight]
+        }
+    }
+
+    pub fn percent_mapping(&self) -> PercentMapping {
+        PercentMapping {
+            scale: self.scale,
+            exponent: self.exponent,
+            rounding: self.rounding,
+        }
+    }
+
+    /// Level icon table in use, if level icons are enabled
+    pub fn level_icons(&self) -> Option<IconTable> {
+        self.icon_levels
+            .clone()
+            .or_else(|| self.level_icons.then(IconTable::default))
+    }
+}
+
+/// Notification settings of a single device, overriding the ones of its class
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Profile {
+    pub title: Option<String>,
+    pub message: Option<String>,
+    pub icon: Option<String>,
+    pub icon_levels: Option<IconTable>,
+    pub icon_up: Option<String>,
+    pub icon_down: Option<String>,
+    pub timeout: Option<u32>,
+    pub urgency: Option<Urgency>,
+    /// Whether changes of the device are notified at all
+    pub enabled: bool,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            title: None,
+            message: None,
+            icon: None,
+            icon_levels: None,
+            icon_up: None,
+            icon_down: None,
+            timeout: None,
+            urgency: None,
+            enabled: true,
+        }
+    }
+}
+
+/// Checks that a number of seconds makes a valid duration
+fn seconds(name: &str, value: f32) -> Result<Duration, String> {
+    Duration::try_from_secs_f32(value).map_err(|_| {
+        format!("{name} must be a finite number of seconds of at least 0, got {value}")
+    })
+}
+
+/// `$XDG_CONFIG_HOME/blight-notify/config.toml`, with `~/.config` as the default config home
+pub fn default_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("blight-notify/config.toml"))
+}
+
+/// Sysfs root set through the environment, which takes precedence over the config file
+pub fn env_sysfs_root() -> Option<PathBuf> {
+    env::var_os(SYSFS_ROOT_ENV).map(PathBuf::from)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Poll,
+    Uevent,
+    Auto,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "poll" => Ok(Backend::Poll),
+            "uevent" => Ok(Backend::Uevent),
+            "auto" => Ok(Backend::Auto),
+            _ => Err(format!(
+                "unknown backend '{s}', expected poll, uevent or auto"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Urgency {
+    Low,
+    Normal,
+    Critical,
+}
+
+impl FromStr for Urgency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(Urgency::Low),
+            "normal" => Ok(Urgency::Normal),
+            "critical" => Ok(Urgency::Critical),
+            _ => Err(format!(
+                "unknown urgency '{s}', expected low, normal or critical"
+            )),
+        }
+    }
+}
+
+/// Where brightness events go, besides the commands run for them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Output {
+    /// Desktop notifications
+    Notify,
+    /// A line per event on stdout
+    Stdout,
+    /// A JSON object per line on stdout
+    Json,
+}
+
+impl FromStr for Output {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "notify" => Ok(Output::Notify),
+            "stdout" => Ok(Output::Stdout),
+            "json" => Ok(Output::Json),
+            _ => Err(format!(
+                "unknown output '{s}', expected notify, stdout or json"
+            )),
+        }
+    }
+}
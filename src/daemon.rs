@@ -0,0 +1,389 @@
+//! Main loop tying a source to the sinks, through the automatic change filter,
+//! the debouncer and the threshold
+
+use crate::{
+    auto::{self, AutoFilter, Verdict},
+    change::Change,
+    config::Config,
+    debounce::Debouncer,
+    device::{Device, DeviceClass},
+    error::BlightError,
+    event::BrightnessEvent,
+    sink::Sink,
+    source::BrightnessSource,
+    threshold::Threshold,
+};
+use log::{debug, error, info, warn};
+use notify::{Config as NotifyConfig, Event, RecommendedWatcher, RecursiveMode, Watcher};
+use signal_hook::{
+    consts::{SIGHUP, SIGINT, SIGTERM},
+    iterator::Signals,
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Messages handled by the main loop
+#[derive(Debug)]
+pub enum Message {
+    Change(Change),
+    /// A signal hinting at automatic brightness changes was received
+    AutoSignal(Instant),
+    /// The configuration should be reloaded
+    Reload,
+    /// The daemon should stop
+    Shutdown,
+}
+
+type Loader = Box<dyn Fn() -> Result<Config, BlightError>>;
+
+/// Events per device
+type Events = HashMap<(DeviceClass, String), BrightnessEvent>;
+
+pub struct Daemon {
+    conf: Config,
+    /// Reads the configuration again on reload
+    loader: Loader,
+    sinks: Vec<Box<dyn Sink>>,
+    sender: Sender<Message>,
+    receiver: Receiver<Message>,
+    /// Kept alive for as long as the daemon runs
+    config_watcher: Option<RecommendedWatcher>,
+}
+
+impl Daemon {
+    pub fn new<L>(conf: Config, loader: L) -> Self
+    where
+        L: Fn() -> Result<Config, BlightError> + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        Daemon {
+            conf,
+            loader: Box::new(loader),
+            sinks: Vec::new(),
+            sender,
+            receiver,
+            config_watcher: None,
+        }
+    }
+
+    pub fn conf(&self) -> &Config {
+        &self.conf
+    }
+
+    /// Handler to create the source with, feeding its changes to the main loop
+    pub fn change_handler(&self) -> impl Fn(Change) + Clone + Send + 'static {
+        let s = self.sender.clone();
+        move |change| {
+            if let Err(err) = s.send(Message::Change(change)) {
+                debug!("change receiver is gone, dropping {:?}", err.0);
+            }
+        }
+    }
+
+    /// Sender for messages from outside the daemon, e.g. to stop it
+    pub fn sender(&self) -> Sender<Message> {
+        self.sender.clone()
+    }
+
+    pub fn add_sink(&mut self, sink: Box<dyn Sink>) {
+        self.sinks.push(sink);
+    }
+
+    /// Requests a reload on SIGHUP and a shutdown on SIGTERM or SIGINT
+    pub fn handle_signals(&self) -> std::io::Result<()> {
+        let mut signals = Signals::new([SIGHUP, SIGTERM, SIGINT])?;
+        let s = self.sender.clone();
+        thread::Builder::new()
+            .name("signal handler".into())
+            .spawn(move || {
+                for signal in signals.forever() {
+                    let msg = if signal == SIGHUP {
+                        info!("SIGHUP received, reloading configuration");
+                        Message::Reload
+                    } else {
+                        info!("signal {signal} received");
+                        Message::Shutdown
+                    };
+                    if s.send(msg).is_err() {
+                        break;
+                    }
+                }
+            })?;
+        Ok(())
+    }
+
+    /// Requests a reload whenever the config file is written. The parent directory is
+    /// watched, so editors replacing the file are handled too
+    pub fn watch_config_file(&mut self, path: PathBuf) {
+        let Some(dir) = path.parent().map(|d| d.to_path_buf()) else {
+            return;
+        };
+        let target = path.clone();
+        let s = self.sender.clone();
+        let watcher = RecommendedWatcher::new(
+            move |ev: notify::Result<Event>| match ev {
+                Ok(ev) if !ev.kind.is_access() && ev.paths.contains(&target) => {
+                    debug!("config file changed: {:?}", ev.kind);
+                    let _ = s.send(Message::Reload);
+                }
+                Ok(_) => (),
+                Err(err) => error!("config watcher error: {err}"),
+            },
+            NotifyConfig::default(),
+        )
+        .and_then(|mut w| w.watch(&dir, RecursiveMode::NonRecursive).map(|_| w));
+        match watcher {
+            Ok(w) => {
+                info!("watching config file: {}", path.display());
+                self.config_watcher = Some(w);
+            }
+            Err(err) => error!("failed to watch config file {}: {err}", path.display()),
+        }
+    }
+
+    /// Runs until a shutdown is requested, then closes the sinks
+    pub fn run(mut self, mut source: impl BrightnessSource) -> Result<(), Box<dyn Error>> {
+        let signal_sender = self.sender.clone();
+        auto::listen_signals(&self.conf.inhibit_signal, move || {
+            signal_sender
+                .send(Message::AutoSignal(Instant::now()))
+                .is_ok()
+        });
+        let mut last_scan = Instant::now();
+        let mut debouncer = Debouncer::new(
+            self.debounce_delay(),
+            self.max_latency(),
+            self.conf.debounce_edge,
+        );
+        let mut threshold = Threshold::new(
+            self.conf.min_delta,
+            self.conf.percent_changes,
+            self.conf.percent_mapping(),
+        );
+        let mut auto = AutoFilter::new(&self.conf);
+        // Last event delivered per device, the previous value of the next one
+        let mut last = Events::new();
+        // Same for the sinks getting every change, which skip the filters
+        let mut latest = Events::new();
+        let current = seed(
+            source.devices(),
+            &self.conf,
+            &mut threshold,
+            &mut auto,
+            &mut last,
+            &mut latest,
+        );
+        for sink in &mut self.sinks {
+            if let Err(err) = sink.init(&current) {
+                error!("{err}");
+            }
+        }
+        loop {
+            let rescan_at = self.rescan_interval().map(|i| last_scan + i);
+            let deadline = [rescan_at, debouncer.next_deadline(), auto.next_deadline()]
+                .into_iter()
+                .flatten()
+                .min();
+            let msg = match deadline {
+                Some(deadline) => {
+                    match self
+                        .receiver
+                        .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                    {
+                        Ok(msg) => Some(msg),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => {
+                            return Err(RecvTimeoutError::Disconnected.into())
+                        }
+                    }
+                }
+                None => Some(self.receiver.recv()?),
+            };
+            let mut due = Vec::new();
+            let mut rescan = rescan_at.is_some_and(|at| at <= Instant::now());
+            match msg {
+                Some(Message::Change(change)) => {
+                    debug!("change detected: {change:?}");
+                    let key = (change.class, change.device.clone());
+                    let event = BrightnessEvent::new(
+                        &change,
+                        latest.get(&key),
+                        &self.conf.percent_mapping(),
+                    );
+                    send(&mut self.sinks, true, &event);
+                    latest.insert(key.clone(), event);
+                    let now = Instant::now();
+                    match auto.check(&change, now) {
+                        Verdict::User => due.extend(debouncer.push(key, change, now)),
+                        Verdict::Held => debug!("holding back possible ramp start: {change:?}"),
+                        Verdict::Automatic(reason) => {
+                            debug!("ignoring automatic change, {reason}: {change:?}");
+                            debouncer.cancel(&key);
+                        }
+                    }
+                }
+                Some(Message::AutoSignal(at)) => auto.signal_received(at),
+                Some(Message::Reload) => match self.reload(&mut source) {
+                    Ok(()) => {
+                        info!("configuration reloaded");
+                        debug!("with {:?}", self.conf);
+                        debouncer.configure(
+                            self.debounce_delay(),
+                            self.max_latency(),
+                            self.conf.debounce_edge,
+                        );
+                        threshold.configure(
+                            self.conf.min_delta,
+                            self.conf.percent_changes,
+                            self.conf.percent_mapping(),
+                        );
+                        auto.configure(&self.conf);
+                        rescan = true;
+                    }
+                    Err(err) => {
+                        error!("failed to reload configuration, keeping the current one: {err}")
+                    }
+                },
+                Some(Message::Shutdown) => break,
+                None => (),
+            }
+            if rescan {
+                match source.rescan() {
+                    // Compared to their current brightness like the devices found at startup
+                    Ok(added) => {
+                        seed(
+                            &added,
+                            &self.conf,
+                            &mut threshold,
+                            &mut auto,
+                            &mut last,
+                            &mut latest,
+                        );
+                    }
+                    Err(err) => error!("device rescan failed: {err}"),
+                }
+                last_scan = Instant::now();
+            }
+            let now = Instant::now();
+            for change in auto.poll(now) {
+                let key = (change.class, change.device.clone());
+                due.extend(debouncer.push(key, change, now));
+            }
+            due.extend(debouncer.poll(now));
+            let mapping = self.conf.percent_mapping();
+            for change in due {
+                if !threshold.accept(&change) {
+                    debug!("ignoring change below threshold: {change:?}");
+                    continue;
+                }
+                let key = (change.class, change.device.clone());
+                let event = BrightnessEvent::new(&change, last.get(&key), &mapping);
+                debug!("delivering {event:?}");
+                send(&mut self.sinks, false, &event);
+                last.insert(key, event);
+            }
+        }
+        info!("shutting down");
+        drop(source);
+        for sink in &mut self.sinks {
+            sink.close();
+        }
+        Ok(())
+    }
+
+    /// Reads the configuration again, keeping the settings the watchers were
+    /// created with, as those only take effect after a restart. Nothing is applied
+    /// unless the sinks and the source all accept the new configuration
+    fn reload(&mut self, source: &mut impl BrightnessSource) -> Result<(), Box<dyn Error>> {
+        let mut conf = (self.loader)()?;
+        let current = &self.conf;
+        if conf.backend != current.backend
+            || conf.pollrate != current.pollrate
+            || conf.actual != current.actual
+            || conf.watch_config != current.watch_config
+            || conf.inhibit_signal != current.inhibit_signal
+            || conf.output != current.output
+            || conf.bar_output != current.bar_output
+        {
+            warn!("backend, pollrate, actual, watch-config, inhibit-signal, output and bar-output changes need a restart");
+            conf.backend = current.backend;
+            conf.pollrate = current.pollrate;
+            conf.actual = current.actual;
+            conf.watch_config = current.watch_config;
+            conf.inhibit_signal = current.inhibit_signal.clone();
+            conf.output = current.output.clone();
+            conf.bar_output = current.bar_output;
+        }
+        for sink in &self.sinks {
+            sink.validate(&conf)?;
+        }
+        source.configure(&conf)?;
+        // Only failures outside of the configuration are left, e.g. a fifo that can't be created
+        for sink in &mut self.sinks {
+            if let Err(err) = sink.configure(&conf) {
+                error!("{err}");
+            }
+        }
+        self.conf = conf;
+        Ok(())
+    }
+
+    fn debounce_delay(&self) -> Duration {
+        Duration::from_millis(self.conf.debounce)
+    }
+
+    fn max_latency(&self) -> Duration {
+        Duration::from_millis(self.conf.max_latency)
+    }
+
+    fn rescan_interval(&self) -> Option<Duration> {
+        (self.conf.rescan > 0.).then(|| Duration::from_secs_f32(self.conf.rescan))
+    }
+}
+
+/// Reads the current brightness of the devices, the base their first change is
+/// compared to, returning it as events
+fn seed(
+    devices: &[Device],
+    conf: &Config,
+    threshold: &mut Threshold,
+    auto: &mut AutoFilter,
+    last: &mut Events,
+    latest: &mut Events,
+) -> Vec<BrightnessEvent> {
+    let mapping = conf.percent_mapping();
+    let mut current = Vec::new();
+    for device in devices {
+        match device.read(conf.actual) {
+            Ok(change) => {
+                threshold.accept(&change);
+                auto.seed(&change, Instant::now());
+                let event = BrightnessEvent::new(&change, None, &mapping);
+                let key = (change.class, change.device);
+                last.insert(key.clone(), event.clone());
+                latest.insert(key, event.clone());
+                current.push(event);
+            }
+            Err(err) => debug!("no current brightness for {}: {err}", device.name),
+        }
+    }
+    current
+}
+
+/// Sends the event to the sinks getting every change, or to the other ones
+fn send(sinks: &mut [Box<dyn Sink>], all_changes: bool, event: &BrightnessEvent) {
+    for sink in sinks
+        .iter_mut()
+        .filter(|s| s.wants_all_changes() == all_changes)
+    {
+        if let Err(err) = sink.send(event) {
+            error!("{err}");
+        }
+    }
+}
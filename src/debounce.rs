@@ -0,0 +1,233 @@
+//! Per key debouncing of brightness changes
+//!
+//! A burst is a series of values for the same key with less than `delay` between
+//! them. Depending on the edge, the first value of a burst is emitted right away
+//! (leading) and the last one once the burst is over (trailing). While a burst goes
+//! on, no value waits for longer than `max_latency`, so a drag still updates live.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    hash::Hash,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Edge {
+    Leading,
+    Trailing,
+    Both,
+}
+
+impl Edge {
+    fn leading(self) -> bool {
+        matches!(self, Edge::Leading | Edge::Both)
+    }
+
+    fn trailing(self) -> bool {
+        matches!(self, Edge::Trailing | Edge::Both)
+    }
+}
+
+impl FromStr for Edge {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "leading" => Ok(Edge::Leading),
+            "trailing" => Ok(Edge::Trailing),
+            "both" => Ok(Edge::Both),
+            _ => Err(format!(
+                "unknown edge '{s}', expected leading, trailing or both"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Edge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Edge::Leading => "leading",
+            Edge::Trailing => "trailing",
+            Edge::Both => "both",
+        };
+        f.write_str(s)
+    }
+}
+
+struct Burst<V> {
+    last_event: Instant,
+    /// Latest value not emitted yet, along with when the oldest value it replaced arrived
+    pending: Option<(V, Instant)>,
+}
+
+pub struct Debouncer<K, V> {
+    delay: Duration,
+    max_latency: Duration,
+    edge: Edge,
+    bursts: HashMap<K, Burst<V>>,
+}
+
+impl<K: Eq + Hash + Clone, V> Debouncer<K, V> {
+    pub fn new(delay: Duration, max_latency: Duration, edge: Edge) -> Self {
+        Debouncer {
+            delay,
+            max_latency,
+            edge,
+            bursts: HashMap::new(),
+        }
+    }
+
+    /// Updates the timings, keeping the bursts in progress
+    pub fn configure(&mut self, delay: Duration, max_latency: Duration, edge: Edge) {
+        self.delay = delay;
+        self.max_latency = max_latency;
+        self.edge = edge;
+    }
+
+    /// Records a value, returning it right away if it starts a burst on the leading edge
+    pub fn push(&mut self, key: K, value: V, now: Instant) -> Option<V> {
+        if let Some(burst) = self.bursts.get_mut(&key) {
+            if now.duration_since(burst.last_event) < self.delay {
+                burst.last_event = now;
+                let since = burst.pending.take().map_or(now, |(_, since)| since);
+                burst.pending = Some((value, since));
+                return None;
+            }
+        }
+        let (emit, pending) = if self.edge.leading() {
+            (Some(value), None)
+        } else {
+            (None, Some((value, now)))
+        };
+        self.bursts.insert(
+            key,
+            Burst {
+                last_event: now,
+                pending,
+            },
+        );
+        emit
+    }
+
+    /// Drops the burst of a key along with its pending value
+    pub fn cancel(&mut self, key: &K) {
+        self.bursts.remove(key);
+    }
+
+    /// Takes the values that are due, either because their burst is over or
+    /// because they have waited for the maximum latency
+    pub fn poll(&mut self, now: Instant) -> Vec<V> {
+        let (delay, max_latency, trailing) = (self.delay, self.max_latency, self.edge.trailing());
+        let mut due = Vec::new();
+        self.bursts.retain(|_, burst| {
+            let over = now.duration_since(burst.last_event) >= delay;
+            if let Some((value, since)) = burst.pending.take() {
+                if (over && trailing) || now.duration_since(since) >= max_latency {
+                    due.push(value);
+                } else if !over {
+                    burst.pending = Some((value, since));
+                }
+            }
+            !over
+        });
+        due
+    }
+
+    /// When `poll` should be called next
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.bursts
+            .values()
+            .map(|burst| {
+                let end = burst.last_event + self.delay;
+                match burst.pending {
+                    Some((_, since)) => end.min(since + self.max_latency),
+                    None => end,
+                }
+            })
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DELAY: Duration = Duration::from_millis(100);
+
+    fn at(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn leading_emits_the_first_value_of_a_burst() {
+        let mut debouncer = Debouncer::new(DELAY, Duration::from_secs(1), Edge::Leading);
+        let t = Instant::now();
+        assert_eq!(debouncer.push("panel", 1, t), Some(1));
+        assert_eq!(debouncer.push("panel", 2, at(t, 50)), None);
+        assert_eq!(debouncer.poll(at(t, 150)), Vec::<i32>::new());
+        assert_eq!(debouncer.next_deadline(), None);
+        assert_eq!(debouncer.push("panel", 3, at(t, 300)), Some(3));
+    }
+
+    #[test]
+    fn trailing_emits_the_last_value_once_the_burst_is_over() {
+        let mut debouncer = Debouncer::new(DELAY, Duration::from_secs(1), Edge::Trailing);
+        let t = Instant::now();
+        assert_eq!(debouncer.push("panel", 1, t), None);
+        assert_eq!(debouncer.push("panel", 2, at(t, 50)), None);
+        assert_eq!(debouncer.next_deadline(), Some(at(t, 150)));
+        assert_eq!(debouncer.poll(at(t, 120)), Vec::<i32>::new());
+        assert_eq!(debouncer.poll(at(t, 150)), [2]);
+        assert_eq!(debouncer.next_deadline(), None);
+    }
+
+    #[test]
+    fn both_emits_the_first_and_last_value() {
+        let mut debouncer = Debouncer::new(DELAY, Duration::from_secs(1), Edge::Both);
+        let t = Instant::now();
+        assert_eq!(debouncer.push("panel", 1, t), Some(1));
+        assert_eq!(debouncer.push("panel", 2, at(t, 50)), None);
+        assert_eq!(debouncer.poll(at(t, 150)), [2]);
+        // A single value isn't emitted twice
+        assert_eq!(debouncer.push("panel", 3, at(t, 300)), Some(3));
+        assert_eq!(debouncer.poll(at(t, 400)), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn bursts_are_per_key() {
+        let mut debouncer = Debouncer::new(DELAY, Duration::from_secs(1), Edge::Leading);
+        let t = Instant::now();
+        assert_eq!(debouncer.push("panel", 1, t), Some(1));
+        assert_eq!(debouncer.push("keyboard", 2, at(t, 10)), Some(2));
+    }
+
+    #[test]
+    fn max_latency_flushes_during_a_continuous_burst() {
+        let mut debouncer = Debouncer::new(DELAY, Duration::from_millis(250), Edge::Trailing);
+        let t = Instant::now();
+        let mut due = Vec::new();
+        for ms in (0..=600).step_by(50) {
+            due.extend(debouncer.push("panel", ms, at(t, ms)));
+            due.extend(debouncer.poll(at(t, ms)));
+        }
+        due.extend(debouncer.poll(at(t, 700)));
+        assert_eq!(due, [250, 550, 600]);
+    }
+
+    #[test]
+    fn cancel_drops_the_pending_value() {
+        let mut debouncer = Debouncer::new(DELAY, Duration::from_secs(1), Edge::Both);
+        let t = Instant::now();
+        assert_eq!(debouncer.push("panel", 1, t), Some(1));
+        assert_eq!(debouncer.push("panel", 2, at(t, 50)), None);
+        debouncer.cancel(&"panel");
+        assert_eq!(debouncer.next_deadline(), None);
+        assert_eq!(debouncer.poll(at(t, 200)), Vec::<i32>::new());
+        // The next value starts a new burst
+        assert_eq!(debouncer.push("panel", 3, at(t, 300)), Some(3));
+    }
+}
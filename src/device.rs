@@ -0,0 +1,334 @@
+use crate::{change::Change, error::BlightError};
+use glob::Pattern;
+use log::debug;
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::SystemTime,
+};
+
+/// Backlight interface type as reported by the kernel in the `type` attribute,
+/// declared in order of preference
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DeviceType {
+    Firmware,
+    Platform,
+    Raw,
+    Unknown,
+}
+
+impl FromStr for DeviceType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "firmware" => DeviceType::Firmware,
+            "platform" => DeviceType::Platform,
+            "raw" => DeviceType::Raw,
+            _ => DeviceType::Unknown,
+        })
+    }
+}
+
+impl fmt::Display for DeviceType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            DeviceType::Firmware => "firmware",
+            DeviceType::Platform => "platform",
+            DeviceType::Raw => "raw",
+            DeviceType::Unknown => "unknown",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Sysfs device class a device is registered under
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DeviceClass {
+    Backlight,
+    /// Keyboard backlights exposed through the leds class
+    Keyboard,
+}
+
+impl DeviceClass {
+    /// Directory of the class under `<root>/class`
+    pub fn dir(self) -> &'static str {
+        match self {
+            DeviceClass::Backlight => "backlight",
+            DeviceClass::Keyboard => "leds",
+        }
+    }
+
+    /// Determines the class from a `<root>/class/<class>/<device>/<attribute>` path
+    pub fn of_path(path: &Path) -> Option<Self> {
+        let class_dir = path.parent()?.parent()?.file_name()?;
+        [DeviceClass::Backlight, DeviceClass::Keyboard]
+            .into_iter()
+            .find(|c| class_dir == c.dir())
+    }
+}
+
+impl fmt::Display for DeviceClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            DeviceClass::Backlight => "backlight",
+            DeviceClass::Keyboard => "keyboard",
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Device {
+    pub name: String,
+    pub path: PathBuf,
+    pub class: DeviceClass,
+    pub kind: DeviceType,
+}
+
+impl Device {
+    fn from_dir(path: PathBuf, class: DeviceClass) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().into_owned();
+        let kind = fs::read_to_string(path.join("type"))
+            .ok()
+            .and_then(|t| t.trim().parse().ok())
+            .unwrap_or(DeviceType::Unknown);
+        Some(Device {
+            name,
+            path,
+            class,
+            kind,
+        })
+    }
+
+    /// Attributes to watch for changes, `actual_brightness` is included when
+    /// requested and present
+    pub fn watch_paths(&self, actual: bool) -> Vec<PathBuf> {
+        let mut paths = vec![self.path.join("brightness")];
+        let actual_path = self.path.join("actual_brightness");
+        if actual && actual_path.exists() {
+            paths.push(actual_path);
+        }
+        paths
+    }
+
+    /// Attributes and sysfs links of the device, whichever can be read
+    pub fn info(&self) -> DeviceInfo {
+        let read = |attr| read_value(&self.path.join(attr)).ok();
+        let sys_path = fs::canonicalize(&self.path).ok();
+        // Backlights of DRM connectors live in a `card<N>-<connector>` directory
+        let connector = sys_path
+            .as_deref()
+            .and_then(|p| p.parent()?.file_name())
+            .and_then(|dir| {
+                let dir = dir.to_str()?.strip_prefix("card")?;
+                let (_, connector) = dir.split_once('-')?;
+                Some(connector.to_owned())
+            });
+        // The driver is bound to the parent device, or to one of its own parents for
+        // connectors and LED controllers
+        let driver = [
+            "device/driver",
+            "device/device/driver",
+            "device/device/device/driver",
+        ]
+        .iter()
+        .find_map(|link| fs::read_link(self.path.join(link)).ok())
+        .and_then(|target| Some(target.file_name()?.to_string_lossy().into_owned()));
+        DeviceInfo {
+            max: read("max_brightness"),
+            brightness: read("brightness"),
+            bl_power: read("bl_power"),
+            sys_path,
+            driver,
+            connector,
+        }
+    }
+
+    /// Current brightness of the device
+    pub fn read(&self, actual: bool) -> Result<Change, BlightError> {
+        Ok(Change {
+            class: self.class,
+            device: self.name.clone(),
+            path: self.path.clone(),
+            raw: read_value(&brightness_file(&self.path, actual))?,
+            max: read_value(&self.path.join("max_brightness"))?,
+            timestamp: SystemTime::now(),
+        })
+    }
+}
+
+/// Device details for diagnostics, attributes missing or unreadable are left out
+#[derive(Debug, Clone, Default)]
+pub struct DeviceInfo {
+    pub max: Option<u64>,
+    pub brightness: Option<u64>,
+    /// Power state of backlights, 0 when on and 4 when powered down
+    pub bl_power: Option<u64>,
+    /// Device directory with symlinks resolved, under `/sys/devices`
+    pub sys_path: Option<PathBuf>,
+    pub driver: Option<String>,
+    pub connector: Option<String>,
+}
+
+/// Reads a numeric brightness attribute
+pub fn read_value(path: &Path) -> Result<u64, BlightError> {
+    let value = fs::read_to_string(path).map_err(|source| BlightError::Read {
+        path: path.into(),
+        source,
+    })?;
+    value.trim().parse().map_err(|_| BlightError::Parse {
+        path: path.into(),
+        value: value.trim().into(),
+    })
+}
+
+/// Attribute of a device directory to read the current brightness from,
+/// `actual_brightness` falls back to `brightness` when absent
+pub fn brightness_file(device_dir: &Path, actual: bool) -> PathBuf {
+    let actual_path = device_dir.join("actual_brightness");
+    if actual && actual_path.exists() {
+        actual_path
+    } else {
+        device_dir.join("brightness")
+    }
+}
+
+/// Lists every device of the given classes registered under the sysfs root
+///
+/// A missing backlight class directory is an error, other classes are optional.
+pub fn discover(sysfs_root: &Path, classes: &[DeviceClass]) -> Result<Vec<Device>, BlightError> {
+    let mut devices = Vec::new();
+    for &class in classes {
+        let found = scan_class(sysfs_root, class)?
+            .into_iter()
+            .filter(|d| class != DeviceClass::Keyboard || d.name.ends_with("kbd_backlight"));
+        devices.extend(found);
+    }
+    Ok(devices)
+}
+
+/// Lists the devices of a class directory sorted by name, including the leds
+/// that are not keyboard backlights
+pub fn scan_class(sysfs_root: &Path, class: DeviceClass) -> Result<Vec<Device>, BlightError> {
+    let class_dir = sysfs_root.join("class").join(class.dir());
+    debug!("scanning {}", class_dir.display());
+    let entries = match fs::read_dir(&class_dir) {
+        Ok(entries) => entries,
+        Err(err) if class != DeviceClass::Backlight && err.kind() == io::ErrorKind::NotFound => {
+            return Ok(Vec::new())
+        }
+        Err(source) => {
+            return Err(BlightError::Discovery {
+                path: class_dir,
+                source,
+            })
+        }
+    };
+    let mut found: Vec<Device> = entries
+        .filter_map(|r| r.ok())
+        .filter_map(|e| Device::from_dir(e.path(), class))
+        .collect();
+    found.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(found)
+}
+
+#[derive(Debug, Default)]
+pub struct DeviceFilter {
+    pub include: Vec<Pattern>,
+    pub exclude: Vec<Pattern>,
+    pub auto_select: bool,
+}
+
+impl DeviceFilter {
+    pub fn matches(&self, device: &Device) -> bool {
+        let included =
+            self.include.is_empty() || self.include.iter().any(|p| p.matches(&device.name));
+        included && !self.exclude.iter().any(|p| p.matches(&device.name))
+    }
+
+    /// Applies the name filters, then, in auto-select mode, keeps only the backlight
+    /// devices of the most preferred type left (firmware > platform > raw)
+    pub fn select(&self, devices: Vec<Device>) -> Vec<Device> {
+        let mut devices: Vec<Device> = devices.into_iter().filter(|d| self.matches(d)).collect();
+        if self.auto_select {
+            let best = devices
+                .iter()
+                .filter(|d| d.class == DeviceClass::Backlight)
+                .map(|d| d.kind)
+                .min();
+            if let Some(best) = best {
+                debug!("auto-selecting {best} devices");
+                devices.retain(|d| d.class != DeviceClass::Backlight || d.kind == best);
+            }
+        }
+        devices
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(name: &str, class: DeviceClass, kind: DeviceType) -> Device {
+        Device {
+            name: name.into(),
+            path: PathBuf::from("/sys/class").join(class.dir()).join(name),
+            class,
+            kind,
+        }
+    }
+
+    fn devices() -> Vec<Device> {
+        vec![
+            device("intel_backlight", DeviceClass::Backlight, DeviceType::Raw),
+            device("acpi_video0", DeviceClass::Backlight, DeviceType::Firmware),
+            device(
+                "dell_backlight",
+                DeviceClass::Backlight,
+                DeviceType::Platform,
+            ),
+            device("kbd_backlight", DeviceClass::Keyboard, DeviceType::Unknown),
+        ]
+    }
+
+    fn filter(include: &[&str], exclude: &[&str], auto_select: bool) -> DeviceFilter {
+        let compile =
+            |patterns: &[&str]| patterns.iter().map(|p| Pattern::new(p).unwrap()).collect();
+        DeviceFilter {
+            include: compile(include),
+            exclude: compile(exclude),
+            auto_select,
+        }
+    }
+
+    fn names(devices: Vec<Device>) -> Vec<String> {
+        devices.into_iter().map(|d| d.name).collect()
+    }
+
+    #[test]
+    fn name_filters() {
+        assert_eq!(names(filter(&[], &[], false).select(devices())).len(), 4);
+        assert_eq!(
+            names(filter(&["*_backlight"], &["kbd*"], false).select(devices())),
+            ["intel_backlight", "dell_backlight"]
+        );
+    }
+
+    #[test]
+    fn auto_select_prefers_firmware_then_platform_then_raw() {
+        assert_eq!(
+            names(filter(&[], &[], true).select(devices())),
+            ["acpi_video0", "kbd_backlight"]
+        );
+        assert_eq!(
+            names(filter(&[], &["acpi*"], true).select(devices())),
+            ["dell_backlight", "kbd_backlight"]
+        );
+        assert_eq!(
+            names(filter(&["intel*"], &[], true).select(devices())),
+            ["intel_backlight"]
+        );
+    }
+}
@@ -0,0 +1,75 @@
+use std::{fmt, io, path::PathBuf};
+
+#[derive(Debug)]
+pub enum BlightError {
+    /// A sysfs attribute could not be read
+    Read {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// A sysfs attribute didn't hold a valid brightness value
+    Parse {
+        path: PathBuf,
+        value: String,
+    },
+    /// A device class directory could not be listed
+    Discovery {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// The config file is not valid
+    Config {
+        path: PathBuf,
+        message: String,
+    },
+    /// The command line options are not valid along with the config file
+    Options(String),
+    /// No device is left to watch after discovery and filtering
+    NoDevices(PathBuf),
+    /// A watcher event didn't carry the path of a device attribute
+    EventPath,
+    Watcher(notify::Error),
+}
+
+impl fmt::Display for BlightError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlightError::Read { path, source } => {
+                write!(f, "failed to read {}: {source}", path.display())
+            }
+            BlightError::Parse { path, value } => {
+                write!(f, "invalid value {value:?} in {}", path.display())
+            }
+            BlightError::Discovery { path, source } => {
+                write!(f, "failed to list devices in {}: {source}", path.display())
+            }
+            BlightError::Config { path, message } => {
+                write!(f, "invalid config file {}: {message}", path.display())
+            }
+            BlightError::Options(message) => write!(f, "invalid options: {message}"),
+            BlightError::NoDevices(path) => {
+                write!(f, "no backlight devices to watch in {}", path.display())
+            }
+            BlightError::EventPath => f.write_str("watcher event without a device attribute path"),
+            BlightError::Watcher(err) => write!(f, "watcher error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for BlightError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BlightError::Read { source, .. } | BlightError::Discovery { source, .. } => {
+                Some(source)
+            }
+            BlightError::Watcher(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<notify::Error> for BlightError {
+    fn from(err: notify::Error) -> Self {
+        BlightError::Watcher(err)
+    }
+}
@@ -0,0 +1,56 @@
+use crate::{
+    change::{Change, Direction},
+    device::DeviceClass,
+    scale::PercentMapping,
+};
+use std::{path::PathBuf, time::SystemTime};
+
+/// Brightness change delivered to the sinks, once debounced and filtered
+#[derive(Debug, Clone, PartialEq)]
+pub struct BrightnessEvent {
+    pub class: DeviceClass,
+    pub device: String,
+    /// Device directory in sysfs
+    pub path: PathBuf,
+    pub raw: u64,
+    pub max: u64,
+    /// Displayed percentage, as given by the configured mapping
+    pub percent: u8,
+    /// Raw brightness of the previous event of the same device
+    pub previous: Option<u64>,
+    /// Change in percent since the previous event of the same device
+    pub delta: i16,
+    pub direction: Direction,
+    /// When the value was read
+    pub timestamp: SystemTime,
+}
+
+impl BrightnessEvent {
+    pub fn new(
+        change: &Change,
+        previous: Option<&BrightnessEvent>,
+        mapping: &PercentMapping,
+    ) -> Self {
+        let percent = mapping.percent(change);
+        BrightnessEvent {
+            class: change.class,
+            device: change.device.clone(),
+            path: change.path.clone(),
+            raw: change.raw,
+            max: change.max,
+            percent,
+            previous: previous.map(|p| p.raw),
+            delta: previous.map_or(0, |p| percent as i16 - p.percent as i16),
+            direction: Direction::between(previous.map(|p| p.ratio()), change.ratio()),
+            timestamp: change.timestamp,
+        }
+    }
+
+    /// Raw brightness relative to the maximum, regardless of the percentage mapping
+    pub fn ratio(&self) -> f64 {
+        if self.max == 0 {
+            return 0.;
+        }
+        self.raw as f64 / self.max as f64
+    }
+}
@@ -0,0 +1,126 @@
+//! Named pipe output for overlay bars such as wob and xob, which read a value per line
+
+use crate::{config::Config, event::BrightnessEvent, sink::Sink, template::Template};
+use log::{debug, info};
+use std::{
+    error::Error,
+    ffi::CString,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    os::unix::{ffi::OsStrExt, fs::FileTypeExt, fs::OpenOptionsExt},
+    path::{Path, PathBuf},
+};
+
+/// Writes a line per event to the configured FIFO, if any. Events are dropped while
+/// no reader is there, and the FIFO is reopened when the reader restarts
+pub struct FifoSink {
+    path: Option<PathBuf>,
+    format: Template,
+    pipe: Option<File>,
+}
+
+impl FifoSink {
+    pub fn new(conf: &Config) -> Result<Self, Box<dyn Error>> {
+        let format = Template::parse(&conf.fifo_format)?;
+        if let Some(path) = &conf.fifo {
+            create_fifo(path)?;
+        }
+        Ok(FifoSink {
+            path: conf.fifo.clone(),
+            format,
+            pipe: None,
+        })
+    }
+}
+
+impl Sink for FifoSink {
+    fn send(&mut self, event: &BrightnessEvent) -> Result<(), Box<dyn Error>> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let line = self.format.render(event) + "\n";
+        // A second attempt in case the reader restarted since the last event
+        for _ in 0..2 {
+            let pipe = match &mut self.pipe {
+                Some(pipe) => pipe,
+                None => match open_fifo(path) {
+                    Ok(pipe) => self.pipe.insert(pipe),
+                    Err(err) if err.raw_os_error() == Some(libc::ENXIO) => {
+                        debug!("no reader on {}, dropping event", path.display());
+                        return Ok(());
+                    }
+                    Err(err) => {
+                        return Err(format!("failed to open {}: {err}", path.display()).into())
+                    }
+                },
+            };
+            match pipe.write_all(line.as_bytes()) {
+                Ok(()) => return Ok(()),
+                Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {
+                    debug!("reader of {} went away, reopening it", path.display());
+                    self.pipe = None;
+                }
+                Err(err) => {
+                    self.pipe = None;
+                    return Err(format!("failed to write to {}: {err}", path.display()).into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn validate(&self, conf: &Config) -> Result<(), Box<dyn Error>> {
+        Template::parse(&conf.fifo_format)?;
+        if let Some(path) = conf.fifo.as_ref().filter(|_| conf.fifo != self.path) {
+            is_fifo(path)?;
+        }
+        Ok(())
+    }
+
+    fn configure(&mut self, conf: &Config) -> Result<(), Box<dyn Error>> {
+        let format = Template::parse(&conf.fifo_format)?;
+        if conf.fifo != self.path {
+            if let Some(path) = &conf.fifo {
+                create_fifo(path)?;
+            }
+            self.path = conf.fifo.clone();
+            self.pipe = None;
+        }
+        self.format = format;
+        Ok(())
+    }
+}
+
+/// Tells whether the FIFO is already there, failing if something else is at the path
+fn is_fifo(path: &Path) -> Result<bool, String> {
+    match fs::metadata(path) {
+        Ok(meta) if meta.file_type().is_fifo() => Ok(true),
+        Ok(_) => Err(format!("{} exists and is not a fifo", path.display())),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(format!("failed to access {}: {err}", path.display())),
+    }
+}
+
+/// Creates the FIFO unless it's already there
+fn create_fifo(path: &Path) -> Result<(), String> {
+    if is_fifo(path)? {
+        return Ok(());
+    }
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| format!("invalid fifo path {}", path.display()))?;
+    // SAFETY: c_path is a valid nul terminated string
+    if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+        let err = io::Error::last_os_error();
+        return Err(format!("failed to create fifo {}: {err}", path.display()));
+    }
+    info!("created fifo {}", path.display());
+    Ok(())
+}
+
+/// Opens the FIFO without blocking, which fails with ENXIO while there's no reader
+fn open_fifo(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+}
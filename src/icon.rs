@@ -0,0 +1,125 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// Icons picked by brightness level, as (minimum percent, icon name) pairs sorted by threshold
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct IconTable(Vec<(u8, String)>);
+
+impl IconTable {
+    /// Icon of the highest threshold not above the given percentage
+    pub fn lookup(&self, percent: u8) -> Option<&str> {
+        self.0
+            .iter()
+            .rev()
+            .find(|(threshold, _)| *threshold <= percent)
+            .map(|(_, icon)| icon.as_str())
+    }
+}
+
+impl Default for IconTable {
+    fn default() -> Self {
+        IconTable(
+            [
+                (0, "display-brightness-off-symbolic"),
+                (1, "display-brightness-low-symbolic"),
+                (34, "display-brightness-medium-symbolic"),
+                (67, "display-brightness-high-symbolic"),
+            ]
+            .into_iter()
+            .map(|(t, icon)| (t, icon.to_owned()))
+            .collect(),
+        )
+    }
+}
+
+/// Parses a comma separated list of `percent:icon` entries, e.g. `0:off-icon,50:high-icon`
+impl FromStr for IconTable {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut entries = s
+            .split(',')
+            .map(|entry| {
+                let (threshold, icon) = entry
+                    .split_once(':')
+                    .ok_or_else(|| format!("expected percent:icon, got '{entry}'"))?;
+                let threshold: u8 = threshold
+                    .trim()
+                    .parse()
+                    .ok()
+                    .filter(|t| *t <= 100)
+                    .ok_or_else(|| format!("invalid percent '{threshold}'"))?;
+                Ok((threshold, icon.trim().to_owned()))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        entries.sort_by_key(|(threshold, _)| *threshold);
+        Ok(IconTable(entries))
+    }
+}
+
+impl TryFrom<String> for IconTable {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for IconTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (threshold, icon)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{threshold}:{icon}")?;
+        }
+        Ok(())
+    }
+}
+
+impl From<IconTable> for String {
+    fn from(table: IconTable) -> Self {
+        table.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_sorts_entries() {
+        let table: IconTable = " 50:high , 0:off,10:low".parse().unwrap();
+        assert_eq!(table.to_string(), "0:off,10:low,50:high");
+    }
+
+    #[test]
+    fn rejects_invalid_entries() {
+        assert_eq!(
+            "0:off,high".parse::<IconTable>(),
+            Err("expected percent:icon, got 'high'".into())
+        );
+        assert_eq!(
+            "101:high".parse::<IconTable>(),
+            Err("invalid percent '101'".into())
+        );
+        assert_eq!(
+            "x:high".parse::<IconTable>(),
+            Err("invalid percent 'x'".into())
+        );
+    }
+
+    #[test]
+    fn lookup_picks_the_highest_threshold_reached() {
+        let table: IconTable = "10:low,50:high".parse().unwrap();
+        assert_eq!(table.lookup(0), None);
+        assert_eq!(table.lookup(10), Some("low"));
+        assert_eq!(table.lookup(49), Some("low"));
+        assert_eq!(table.lookup(100), Some("high"));
+        let default = IconTable::default();
+        assert_eq!(default.lookup(0), Some("display-brightness-off-symbolic"));
+        assert_eq!(default.lookup(33), Some("display-brightness-low-symbolic"));
+        assert_eq!(default.lookup(67), Some("display-brightness-high-symbolic"));
+    }
+}
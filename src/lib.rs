@@ -0,0 +1,27 @@
+//! Backlight change detection behind the blight-notify daemon, usable on its own
+//! with custom sources and sinks
+
+pub mod auto;
+pub mod bar;
+pub mod change;
+pub mod config;
+pub mod daemon;
+pub mod debounce;
+pub mod device;
+pub mod error;
+pub mod event;
+pub mod fifo;
+pub mod icon;
+pub mod notification;
+pub mod scale;
+pub mod sink;
+pub mod source;
+pub mod template;
+pub mod threshold;
+pub mod uevent;
+
+pub use daemon::Daemon;
+pub use error::BlightError;
+pub use event::BrightnessEvent;
+pub use sink::Sink;
+pub use source::BrightnessSource;
@@ -1,169 +1,468 @@
+mod commands;
+
 use argh::FromArgs;
+use blight_notify::{
+    bar::{BarFormat, BarSink},
+    config::{self, Backend, Config, Output, Urgency},
+    debounce::Edge,
+    fifo::FifoSink,
+    icon::IconTable,
+    notification::NotificationSink,
+    scale::{Rounding, Scale},
+    sink::{ExecSink, JsonSink, Sink, StdoutSink},
+    source::SysfsSource,
+    threshold::MinDelta,
+    BlightError, Daemon,
+};
+use commands::Command;
 use env_logger::Env;
 use log::{debug, error, info};
-use notify::{Config as NotifyConfig, Event, PollWatcher, RecursiveMode, Watcher};
-use notify_rust::{error::Error as NotifyError, Notification, Timeout, Urgency};
-use std::{
-    error::Error,
-    path::{Path, PathBuf},
-    sync::mpsc,
-    thread,
-    time::Duration,
-};
+use std::{error::Error, path::PathBuf};
 
 #[derive(FromArgs, Debug)]
 #[argh(description = "A simple backlight notification daemon")]
-struct Config {
+struct Args {
+    #[argh(
+        option,
+        short = 'c',
+        description = "set config file (default: $XDG_CONFIG_HOME/blight-notify/config.toml)"
+    )]
+    config: Option<PathBuf>,
+    #[argh(switch, description = "print the effective configuration and exit")]
+    print_config: bool,
+    #[argh(
+        switch,
+        short = 'w',
+        description = "reload the configuration when the config file changes (SIGHUP always reloads)"
+    )]
+    watch_config: bool,
+    #[argh(
+        switch,
+        description = "don't reload on config file changes, overriding the config file"
+    )]
+    no_watch_config: bool,
     #[argh(
         option,
-        default = "String::from(\"Blight\")",
         short = 't',
-        description = "set notification title"
+        description = "set notification title template (default: Blight)"
     )]
-    title: String,
+    title: Option<String>,
     #[argh(
         option,
-        default = "String::from(\"Brightness adjusted:\")",
         short = 'm',
-        description = "set notification message"
+        description = "set notification message template, placeholders: {{percent}} {{raw}} {{max}} {{device}} {{delta}} {{direction}} {{bar}}"
     )]
-    message: String,
+    message: Option<String>,
     #[argh(option, short = 'i', description = "set icon name/location")]
     icon: Option<String>,
+    #[argh(
+        switch,
+        short = 'l',
+        description = "pick the icon by brightness level (display-brightness-*-symbolic by default)"
+    )]
+    level_icons: bool,
+    #[argh(
+        switch,
+        description = "don't pick the icon by brightness level, overriding the config file"
+    )]
+    no_level_icons: bool,
+    #[argh(
+        option,
+        description = "set level icons as percent:icon pairs, e.g. 0:off,50:high (implies --level-icons)"
+    )]
+    icon_levels: Option<IconTable>,
+    #[argh(option, description = "set icon shown when brightness goes up")]
+    icon_up: Option<String>,
+    #[argh(option, description = "set icon shown when brightness goes down")]
+    icon_down: Option<String>,
     #[argh(
         option,
         short = 'T',
-        default = "1000",
-        description = "set notification timeout in milliseconds"
+        description = "set notification timeout in milliseconds (default: 1000)"
     )]
-    timeout: u32,
+    timeout: Option<u32>,
+    #[argh(
+        option,
+        description = "set notification urgency: low (default), normal or critical"
+    )]
+    urgency: Option<Urgency>,
     #[argh(
         option,
         short = 'p',
-        default = "0.5f32",
-        description = "set backlight change watcher polling rate"
+        description = "set backlight change watcher polling rate (default: 0.5)"
+    )]
+    pollrate: Option<f32>,
+    #[argh(
+        option,
+        description = "set quiet period in milliseconds after which a burst of changes is over (default: 100)"
+    )]
+    debounce: Option<u64>,
+    #[argh(
+        option,
+        description = "set maximum delay in milliseconds of a notification during a burst (default: 250)"
+    )]
+    max_latency: Option<u64>,
+    #[argh(
+        option,
+        description = "notify at the leading edge of a burst, the trailing edge or both (default)"
+    )]
+    debounce_edge: Option<Edge>,
+    #[argh(
+        option,
+        description = "set percentage scale: linear (default) or perceptual, spreading out low levels"
+    )]
+    scale: Option<Scale>,
+    #[argh(
+        option,
+        description = "set exponent of the perceptual scale (default: 4)"
+    )]
+    exponent: Option<f64>,
+    #[argh(
+        option,
+        description = "set how percentages are rounded: truncate (default) or round"
+    )]
+    rounding: Option<Rounding>,
+    #[argh(
+        option,
+        description = "set minimum change to notify about, in percent (e.g. 2%) or raw steps (e.g. 10)"
+    )]
+    min_delta: Option<MinDelta>,
+    #[argh(
+        switch,
+        description = "only notify when the displayed integer percentage changes"
+    )]
+    percent_changes: bool,
+    #[argh(
+        switch,
+        description = "notify on every change, overriding the config file"
+    )]
+    no_percent_changes: bool,
+    #[argh(
+        option,
+        description = "ignore smooth ramps of at least this many small steps, as made by ambient light daemons, 0 to disable (default: 0)"
+    )]
+    ramp_steps: Option<usize>,
+    #[argh(
+        option,
+        description = "set largest step in percent still part of a ramp (default: 2)"
+    )]
+    ramp_max_step: Option<f64>,
+    #[argh(
+        option,
+        description = "set longest gap in milliseconds between two steps of a ramp, small steps are held back this long (default: 200)"
+    )]
+    ramp_max_gap: Option<u64>,
+    #[argh(
+        option,
+        description = "ignore changes while a process with this name is running (repeatable)"
+    )]
+    inhibit_process: Vec<String>,
+    #[argh(
+        option,
+        description = "ignore changes shortly after a signal of this D-Bus interface (repeatable)"
+    )]
+    inhibit_signal: Vec<String>,
+    #[argh(
+        option,
+        description = "set how long in milliseconds changes are ignored after an inhibiting signal (default: 1000)"
+    )]
+    signal_window: Option<u64>,
+    #[argh(
+        option,
+        short = 'b',
+        description = "set change detection backend: poll, uevent or auto (uevent with polling fallback, default)"
+    )]
+    backend: Option<Backend>,
+    #[argh(
+        option,
+        short = 'r',
+        description = "set device rescan interval in seconds for hotplug detection, 0 to disable (default: 5)"
+    )]
+    rescan: Option<f32>,
+    #[argh(
+        option,
+        description = "set sysfs root directory (env: BLIGHT_NOTIFY_SYSFS_ROOT, default: /sys)"
+    )]
+    sysfs_root: Option<PathBuf>,
+    #[argh(
+        option,
+        description = "only watch devices matching name or glob (repeatable)"
+    )]
+    device: Vec<String>,
+    #[argh(
+        option,
+        description = "ignore devices matching name or glob (repeatable)"
+    )]
+    exclude_device: Vec<String>,
+    #[argh(
+        switch,
+        short = 'a',
+        description = "only watch the preferred device type (firmware > platform > raw)"
+    )]
+    auto_select: bool,
+    #[argh(
+        switch,
+        description = "watch every device type, overriding the config file"
     )]
-    pollrate: f32,
+    no_auto_select: bool,
+    #[argh(
+        switch,
+        description = "compute brightness from actual_brightness as reported by the hardware and watch it too"
+    )]
+    actual: bool,
+    #[argh(
+        switch,
+        description = "compute brightness from the brightness attribute, overriding the config file"
+    )]
+    no_actual: bool,
+    #[argh(
+        switch,
+        short = 'k',
+        description = "also watch keyboard backlight leds"
+    )]
+    keyboard: bool,
+    #[argh(
+        switch,
+        description = "don't watch keyboard backlight leds, overriding the config file"
+    )]
+    no_keyboard: bool,
+    #[argh(
+        option,
+        description = "set keyboard backlight notification title template"
+    )]
+    kbd_title: Option<String>,
+    #[argh(
+        option,
+        description = "set keyboard backlight notification message template"
+    )]
+    kbd_message: Option<String>,
+    #[argh(option, description = "set keyboard backlight icon name/location")]
+    kbd_icon: Option<String>,
+    #[argh(
+        switch,
+        description = "don't set the value hint used by notification servers to show a progress bar"
+    )]
+    no_progress: bool,
+    #[argh(
+        switch,
+        description = "set the progress bar value hint, overriding the config file"
+    )]
+    progress: bool,
+    #[argh(
+        option,
+        description = "send changes to notify (default), stdout or json, repeatable to combine them"
+    )]
+    output: Vec<Output>,
+    #[argh(
+        option,
+        description = "set template of the stdout output lines (default: {{device}}: {{percent}}%)"
+    )]
+    stdout_format: Option<String>,
+    #[argh(
+        option,
+        description = "run a command on each change, with the same placeholders as the message, e.g. \"notify-send {{percent}}\" (repeatable)"
+    )]
+    exec: Vec<String>,
+    #[argh(
+        option,
+        description = "write changes to a fifo read by an overlay bar such as wob or xob, created if missing"
+    )]
+    fifo: Option<PathBuf>,
+    #[argh(
+        option,
+        description = "set template of the fifo lines, e.g. to add wob colors (default: {{percent}})"
+    )]
+    fifo_format: Option<String>,
+    #[argh(
+        option,
+        description = "stream the current state and then every change, unfiltered, for a status bar: waybar, i3bar or plain, replacing notifications unless an output is set, which cannot be stdout or json"
+    )]
+    bar_output: Option<BarFormat>,
+    #[argh(
+        option,
+        description = "set template of the status bar text (default: {{percent}}%)"
+    )]
+    bar_text: Option<String>,
     #[argh(switch, short = 'q', description = "disable logging")]
     quiet: bool,
     #[argh(switch, short = 'd', description = "enable debug level logging")]
     debug: bool,
+    #[argh(subcommand)]
+    command: Option<Command>,
+}
+
+impl Args {
+    /// Overrides the config file values with the options given on the command line
+    fn apply(&self, conf: &mut Config) {
+        macro_rules! set {
+            ($($field:ident),*) => {$(
+                if let Some(v) = &self.$field {
+                    conf.$field = v.clone();
+                }
+            )*};
+        }
+        macro_rules! set_opt {
+            ($($field:ident),*) => {$(
+                if self.$field.is_some() {
+                    conf.$field = self.$field.clone();
+                }
+            )*};
+        }
+        macro_rules! toggle {
+            ($($field:ident / $off:ident),*) => {$(
+                if self.$off {
+                    conf.$field = false;
+                } else if self.$field {
+                    conf.$field = true;
+                }
+            )*};
+        }
+        set!(
+            title,
+            message,
+            timeout,
+            urgency,
+            pollrate,
+            debounce,
+            max_latency,
+            debounce_edge,
+            scale,
+            exponent,
+            rounding,
+            ramp_steps,
+            ramp_max_step,
+            ramp_max_gap,
+            signal_window,
+            backend,
+            rescan,
+            kbd_title,
+            kbd_message,
+            stdout_format,
+            fifo_format,
+            bar_text
+        );
+        set_opt!(
+            icon,
+            icon_levels,
+            icon_up,
+            icon_down,
+            kbd_icon,
+            min_delta,
+            fifo,
+            bar_output
+        );
+        toggle!(
+            level_icons / no_level_icons,
+            auto_select / no_auto_select,
+            actual / no_actual,
+            keyboard / no_keyboard,
+            no_progress / progress,
+            percent_changes / no_percent_changes,
+            watch_config / no_watch_config
+        );
+        if let Some(root) = self.sysfs_root.clone().or_else(config::env_sysfs_root) {
+            conf.sysfs_root = root;
+        }
+        if !self.device.is_empty() {
+            conf.device = self.device.clone();
+        }
+        if !self.exclude_device.is_empty() {
+            conf.exclude_device = self.exclude_device.clone();
+        }
+        if !self.output.is_empty() {
+            conf.output = Some(self.output.clone());
+        }
+        if !self.exec.is_empty() {
+            conf.exec = self.exec.clone();
+        }
+        if !self.inhibit_process.is_empty() {
+            conf.inhibit_process = self.inhibit_process.clone();
+        }
+        if !self.inhibit_signal.is_empty() {
+            conf.inhibit_signal = self.inhibit_signal.clone();
+        }
+    }
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let conf: Config = argh::from_env();
-    if !conf.quiet {
-        init_logging(conf.debug);
+    let args: Args = argh::from_env();
+    if !args.quiet && !args.print_config {
+        init_logging(args.debug);
+    }
+    let conf = match load_config(&args) {
+        Ok(conf) => conf,
+        Err(err) => {
+            error!("{err}");
+            return Err(err.into());
+        }
+    };
+    if args.print_config {
+        print!("{}", conf.to_toml());
+        return Ok(());
+    }
+    if let Some(command) = &args.command {
+        return command.run(&conf).map_err(|err| {
+            error!("{err}");
+            err
+        });
     }
     info!("blight-notify daemon started");
     debug!("with {conf:?}");
-    let (mut watcher, r) = match init_watcher(conf.pollrate) {
-        Ok(w) => w,
+    let sinks = match init_sinks(&conf) {
+        Ok(sinks) => sinks,
         Err(err) => {
             error!("{err}");
-            return Err("failed to initialize watcher".into());
+            return Err(err);
         }
     };
-    watch(&mut watcher)?;
-    loop {
-        let v = r.recv()?;
-        let spam = if let Ok(x) = r.try_recv() {
-            let s = (0..10)
-                .filter_map(|_| {
-                    thread::sleep(Duration::from_millis(150));
-                    r.try_recv().ok()
-                })
-                .last();
-            s.or(Some(x))
-        } else {
-            None
-        };
-        debug!("change detected: received value {v}, spam: {:?}", spam);
-        let fval = spam.unwrap_or(v);
-        let message = format!("{} {}%", conf.message, (fval * 100.) as u8);
-        debug!("sending message: {message}");
-        if let Err(error) = notify(&message, &conf.title, conf.icon.as_ref(), conf.timeout) {
-            error!("{error}");
+    let config_path = args.config.clone().or_else(config::default_path);
+    let mut daemon = Daemon::new(conf, move || load_config(&args));
+    let source = match SysfsSource::new(daemon.conf(), daemon.change_handler()) {
+        Ok(source) => source,
+        Err(err) => {
+            error!("{err}");
+            return Err(err);
         }
+    };
+    for sink in sinks {
+        daemon.add_sink(sink);
     }
+    daemon.handle_signals()?;
+    if daemon.conf().watch_config {
+        if let Some(path) = config_path {
+            daemon.watch_config_file(path);
+        }
+    }
+    daemon.run(source)
 }
 
-fn init_logging(debug: bool) {
-    let level = if debug { "debug" } else { "info" };
-    let env = Env::new().filter_or("RUST_LOG", level);
-    env_logger::init_from_env(env);
+fn load_config(args: &Args) -> Result<Config, BlightError> {
+    let mut conf = Config::load(args.config.as_deref())?;
+    args.apply(&mut conf);
+    conf.validate().map_err(BlightError::Options)?;
+    Ok(conf)
 }
 
-fn notify(
-    message: &str,
-    title: &str,
-    icon: Option<&String>,
-    timeout: u32,
-) -> Result<(), NotifyError> {
-    let mut notif = Notification::new();
-    notif
-        .timeout(Timeout::Milliseconds(timeout))
-        .urgency(Urgency::Low)
-        .id(696969)
-        .appname("Blight notify")
-        .summary(title)
-        .body(message);
-    if let Some(icon_path) = icon {
-        notif.icon(&icon_path);
-    } else {
-        notif.auto_icon();
+fn init_sinks(conf: &Config) -> Result<Vec<Box<dyn Sink>>, Box<dyn Error>> {
+    let mut sinks: Vec<Box<dyn Sink>> = Vec::new();
+    for output in conf.outputs() {
+        match output {
+            Output::Notify => sinks.push(Box::new(NotificationSink::new(conf)?)),
+            Output::Stdout => sinks.push(Box::new(StdoutSink::new(conf)?)),
+            Output::Json => sinks.push(Box::new(JsonSink)),
+        }
     }
-    notif.show()?;
-    Ok(())
-}
-
-fn watch(watcher: &mut impl Watcher) -> notify::Result<()> {
-    let bl_paths: Vec<PathBuf> = std::fs::read_dir("/sys/class/backlight")
-        .unwrap()
-        .filter_map(|r| r.ok())
-        .map(|e| {
-            let mut p = e.path();
-            p.push("brightness");
-            p
-        })
-        .collect();
-
-    for p in bl_paths {
-        watcher.watch(&p, RecursiveMode::NonRecursive)?;
-        info!("watching: {}", p.display());
+    if let Some(format) = conf.bar_output {
+        sinks.push(Box::new(BarSink::new(format, conf)?));
     }
-    Ok(())
+    // Always there, so commands and fifos added on reload take effect
+    sinks.push(Box::new(ExecSink::new(conf)?));
+    sinks.push(Box::new(FifoSink::new(conf)?));
+    Ok(sinks)
 }
 
-fn init_watcher(poll_rate: f32) -> notify::Result<(impl Watcher, mpsc::Receiver<f64>)> {
-    let (s, r) = mpsc::channel::<f64>();
-    let watcher = PollWatcher::new(
-        move |ev| handler(ev, s.clone()),
-        NotifyConfig::default()
-            .with_compare_contents(true)
-            .with_poll_interval(Duration::from_secs_f32(poll_rate)),
-    )
-    .unwrap();
-    Ok((watcher, r))
-}
-
-fn handler(ev: notify::Result<Event>, s: mpsc::Sender<f64>) {
-    let read_val = |path: &Path| {
-        std::fs::read_to_string(path)
-            .unwrap()
-            .trim()
-            .parse()
-            .unwrap()
-    };
-
-    if let Ok(mut event) = ev {
-        let mut p = event.paths.pop().unwrap();
-        let b: f64 = read_val(&p);
-        p.set_file_name("max_brightness");
-        let max: f64 = read_val(&p);
-        let perc = b / max;
-        s.send(perc).unwrap();
-    }
+fn init_logging(debug: bool) {
+    let level = if debug { "debug" } else { "info" };
+    let env = Env::new().filter_or("RUST_LOG", level);
+    env_logger::init_from_env(env);
 }
-
-// TODO: Use logging
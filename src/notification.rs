@@ -0,0 +1,234 @@
+use crate::{
+    change::Direction,
+    config::{Config, Profile, Urgency},
+    device::DeviceClass,
+    event::BrightnessEvent,
+    icon::IconTable,
+    sink::Sink,
+    template::Template,
+};
+use log::debug;
+use notify_rust::{
+    error::Error as NotifyError, Hint, Notification, NotificationHandle, Timeout,
+    Urgency as NotifyUrgency,
+};
+use std::{collections::HashMap, error::Error};
+
+/// Appearance of the notifications of a device class, or of a device with a profile
+#[derive(Debug, Clone)]
+struct Style {
+    title: Template,
+    message: Template,
+    icon: Option<String>,
+    icon_up: Option<String>,
+    icon_down: Option<String>,
+    level_icons: Option<IconTable>,
+    timeout: u32,
+    urgency: Urgency,
+    enabled: bool,
+}
+
+impl Style {
+    fn backlight(conf: &Config) -> Result<Self, String> {
+        Ok(Style {
+            title: Template::parse(&conf.title)?,
+            message: Template::parse(&conf.message)?.with_default_percent(),
+            icon: conf.icon.clone(),
+            icon_up: conf.icon_up.clone(),
+            icon_down: conf.icon_down.clone(),
+            level_icons: conf.level_icons(),
+            timeout: conf.timeout,
+            urgency: conf.urgency,
+            enabled: true,
+        })
+    }
+
+    fn keyboard(conf: &Config) -> Result<Self, String> {
+        Ok(Style {
+            title: Template::parse(&conf.kbd_title)?,
+            message: Template::parse(&conf.kbd_message)?.with_default_percent(),
+            icon: conf.kbd_icon.clone(),
+            icon_up: None,
+            icon_down: None,
+            level_icons: None,
+            timeout: conf.timeout,
+            urgency: conf.urgency,
+            enabled: true,
+        })
+    }
+
+    fn with_profile(&self, profile: &Profile) -> Result<Self, String> {
+        let mut style = self.clone();
+        if let Some(title) = &profile.title {
+            style.title = Template::parse(title)?;
+        }
+        if let Some(message) = &profile.message {
+            style.message = Template::parse(message)?.with_default_percent();
+        }
+        macro_rules! set_opt {
+            ($($field:ident),*) => {$(
+                if profile.$field.is_some() {
+                    style.$field = profile.$field.clone();
+                }
+            )*};
+        }
+        set_opt!(icon, icon_up, icon_down);
+        if profile.icon_levels.is_some() {
+            style.level_icons = profile.icon_levels.clone();
+        }
+        style.timeout = profile.timeout.unwrap_or(style.timeout);
+        style.urgency = profile.urgency.unwrap_or(style.urgency);
+        style.enabled = profile.enabled;
+        Ok(style)
+    }
+
+    /// Direction icons take precedence over level icons, which take precedence
+    /// over the fixed icon
+    fn icon(&self, percent: u8, direction: Direction) -> Option<&str> {
+        let direction_icon = match direction {
+            Direction::Up => self.icon_up.as_deref(),
+            Direction::Down => self.icon_down.as_deref(),
+            Direction::Unchanged => None,
+        };
+        direction_icon
+            .or_else(|| self.level_icons.as_ref().and_then(|l| l.lookup(percent)))
+            .or(self.icon.as_deref())
+    }
+}
+
+/// Desktop notifications, keeping track of what was last shown per device class
+pub struct NotificationSink {
+    backlight: Style,
+    keyboard: Style,
+    /// Styles of the devices with a profile
+    profiles: HashMap<(DeviceClass, String), Style>,
+    no_progress: bool,
+    /// Last shown notification per device class, closed on shutdown
+    shown: HashMap<DeviceClass, NotificationHandle>,
+}
+
+impl NotificationSink {
+    pub fn new(conf: &Config) -> Result<Self, String> {
+        let backlight = Style::backlight(conf)?;
+        let keyboard = Style::keyboard(conf)?;
+        // Profiles apply to whichever class the device turns out to be in, so both are resolved
+        let mut profiles = HashMap::new();
+        for (device, profile) in &conf.profile {
+            let invalid = |err| format!("invalid profile of {device}: {err}");
+            profiles.insert(
+                (DeviceClass::Backlight, device.clone()),
+                backlight.with_profile(profile).map_err(invalid)?,
+            );
+            profiles.insert(
+                (DeviceClass::Keyboard, device.clone()),
+                keyboard.with_profile(profile).map_err(invalid)?,
+            );
+        }
+        Ok(NotificationSink {
+            backlight,
+            keyboard,
+            profiles,
+            no_progress: conf.no_progress,
+            shown: HashMap::new(),
+        })
+    }
+
+    fn style(&self, event: &BrightnessEvent) -> &Style {
+        let default = match event.class {
+            DeviceClass::Backlight => &self.backlight,
+            DeviceClass::Keyboard => &self.keyboard,
+        };
+        self.profiles
+            .get(&(event.class, event.device.clone()))
+            .unwrap_or(default)
+    }
+}
+
+impl Sink for NotificationSink {
+    fn send(&mut self, event: &BrightnessEvent) -> Result<(), Box<dyn Error>> {
+        let class = event.class;
+        let style = self.style(event);
+        if !style.enabled {
+            debug!("notifications of {} are disabled", event.device);
+            return Ok(());
+        }
+        let (title, message) = (style.title.render(event), style.message.render(event));
+        let icon = style.icon(event.percent, event.direction);
+        debug!("sending {class} message: {message}, icon: {icon:?}");
+        let value = (!self.no_progress).then_some(event.percent);
+        let id = notification_id(class);
+        let handle = notify(
+            &message,
+            &title,
+            icon,
+            style.timeout,
+            style.urgency,
+            id,
+            value,
+        )?;
+        self.shown.insert(class, handle);
+        Ok(())
+    }
+
+    fn validate(&self, conf: &Config) -> Result<(), Box<dyn Error>> {
+        NotificationSink::new(conf)?;
+        Ok(())
+    }
+
+    fn configure(&mut self, conf: &Config) -> Result<(), Box<dyn Error>> {
+        let new = NotificationSink::new(conf)?;
+        self.backlight = new.backlight;
+        self.keyboard = new.keyboard;
+        self.profiles = new.profiles;
+        self.no_progress = new.no_progress;
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        for (class, handle) in self.shown.drain() {
+            debug!("closing {class} notification");
+            handle.close();
+        }
+    }
+}
+
+/// Keeps a separate notification per device class so they replace only themselves
+fn notification_id(class: DeviceClass) -> u32 {
+    match class {
+        DeviceClass::Backlight => 696969,
+        DeviceClass::Keyboard => 696970,
+    }
+}
+
+fn notify(
+    message: &str,
+    title: &str,
+    icon: Option<&str>,
+    timeout: u32,
+    urgency: Urgency,
+    id: u32,
+    value: Option<u8>,
+) -> Result<NotificationHandle, NotifyError> {
+    let mut notif = Notification::new();
+    notif
+        .timeout(Timeout::Milliseconds(timeout))
+        .urgency(match urgency {
+            Urgency::Low => NotifyUrgency::Low,
+            Urgency::Normal => NotifyUrgency::Normal,
+            Urgency::Critical => NotifyUrgency::Critical,
+        })
+        .id(id)
+        .appname("Blight notify")
+        .summary(title)
+        .body(message);
+    if let Some(icon_path) = icon {
+        notif.icon(icon_path);
+    } else {
+        notif.auto_icon();
+    }
+    if let Some(value) = value {
+        // Rendered as a progress bar by most notification servers
+        notif.hint(Hint::CustomInt("value".into(), value.into()));
+    }
+    notif.show()
+}
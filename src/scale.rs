@@ -0,0 +1,96 @@
+//! Mapping of raw brightness values to the displayed percentage
+
+use crate::change::Change;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scale {
+    Linear,
+    /// `(raw / max) ^ (1 / exponent)`, as brightnessctl's exponent mode, which spreads
+    /// out the low levels of panels with a large raw range
+    Perceptual,
+}
+
+impl FromStr for Scale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Scale::Linear),
+            "perceptual" | "log" => Ok(Scale::Perceptual),
+            _ => Err(format!(
+                "unknown scale '{s}', expected linear or perceptual"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Scale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Scale::Linear => "linear",
+            Scale::Perceptual => "perceptual",
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rounding {
+    Truncate,
+    Round,
+}
+
+impl FromStr for Rounding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "truncate" => Ok(Rounding::Truncate),
+            "round" => Ok(Rounding::Round),
+            _ => Err(format!(
+                "unknown rounding '{s}', expected truncate or round"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Rounding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Rounding::Truncate => "truncate",
+            Rounding::Round => "round",
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PercentMapping {
+    pub scale: Scale,
+    pub exponent: f64,
+    pub rounding: Rounding,
+}
+
+impl PercentMapping {
+    /// Position of the brightness on the displayed scale, from 0 to 1
+    pub fn ratio(&self, change: &Change) -> f64 {
+        let ratio = change.ratio();
+        match self.scale {
+            Scale::Linear => ratio,
+            Scale::Perceptual if self.exponent > 0. => ratio.powf(self.exponent.recip()),
+            Scale::Perceptual => ratio,
+        }
+    }
+
+    pub fn percent(&self, change: &Change) -> u8 {
+        let percent = self.ratio(change) * 100.;
+        match self.rounding {
+            Rounding::Truncate => percent as u8,
+            Rounding::Round => percent.round() as u8,
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use crate::{config::Config, event::BrightnessEvent, template::Template};
+use log::{debug, warn};
+use serde_json::json;
+use std::{
+    error::Error,
+    io::{self, Write},
+    process::{Command, Stdio},
+    thread,
+    time::UNIX_EPOCH,
+};
+
+/// Output brightness events are delivered to, such as desktop notifications
+pub trait Sink {
+    /// Called once at startup with the current brightness of every device
+    fn init(&mut self, _current: &[BrightnessEvent]) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn send(&mut self, event: &BrightnessEvent) -> Result<(), Box<dyn Error>>;
+
+    /// Whether the sink reflects the current state and so gets every change right
+    /// away, skipping the automatic change filter, the debouncer and the threshold
+    fn wants_all_changes(&self) -> bool {
+        false
+    }
+
+    /// Checks that `configure` would accept a reloaded configuration. Every sink is
+    /// checked before any of them is configured, so a reload applies to all or none
+    fn validate(&self, _conf: &Config) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// Applies a reloaded configuration, leaving the sink untouched on error
+    fn configure(&mut self, _conf: &Config) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// Called once when the daemon stops
+    fn close(&mut self) {}
+}
+
+/// Prints a line per event, e.g. for scripts reading the output
+pub struct StdoutSink {
+    format: Template,
+}
+
+impl StdoutSink {
+    pub fn new(conf: &Config) -> Result<Self, String> {
+        Ok(StdoutSink {
+            format: Template::parse(&conf.stdout_format)?,
+        })
+    }
+}
+
+impl Sink for StdoutSink {
+    fn send(&mut self, event: &BrightnessEvent) -> Result<(), Box<dyn Error>> {
+        writeln!(io::stdout(), "{}", self.format.render(event))?;
+        Ok(())
+    }
+
+    fn validate(&self, conf: &Config) -> Result<(), Box<dyn Error>> {
+        StdoutSink::new(conf)?;
+        Ok(())
+    }
+
+    fn configure(&mut self, conf: &Config) -> Result<(), Box<dyn Error>> {
+        *self = StdoutSink::new(conf)?;
+        Ok(())
+    }
+}
+
+/// Prints each event as a JSON object on its own line
+pub struct JsonSink;
+
+impl Sink for JsonSink {
+    fn send(&mut self, event: &BrightnessEvent) -> Result<(), Box<dyn Error>> {
+        writeln!(io::stdout(), "{}", event_json(event))?;
+        Ok(())
+    }
+}
+
+pub fn event_json(event: &BrightnessEvent) -> serde_json::Value {
+    let timestamp = event
+        .timestamp
+        .duration_since(UNIX_EPOCH)
+        .map_or(0., |t| t.as_secs_f64());
+    json!({
+        "class": event.class.to_string(),
+        "device": event.device,
+        "path": event.path,
+        "raw": event.raw,
+        "max": event.max,
+        "percent": event.percent,
+        "previous": event.previous,
+        "delta": event.delta,
+        "direction": event.direction.to_string(),
+        "timestamp": timestamp,
+    })
+}
+
+/// Runs commands on each event, with the placeholders of their arguments filled in.
+/// Commands are split like a shell would, but not run through one
+pub struct ExecSink {
+    commands: Vec<Vec<Template>>,
+}
+
+impl ExecSink {
+    pub fn new(conf: &Config) -> Result<Self, String> {
+        let commands = conf
+            .exec
+            .iter()
+            .map(|cmd| {
+                let words = shell_words::split(cmd)
+                    .map_err(|err| format!("invalid command '{cmd}': {err}"))?;
+                if words.is_empty() {
+                    return Err("empty command".to_owned());
+                }
+                words.iter().map(|w| Template::parse(w)).collect()
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(ExecSink { commands })
+    }
+}
+
+impl Sink for ExecSink {
+    fn send(&mut self, event: &BrightnessEvent) -> Result<(), Box<dyn Error>> {
+        for command in &self.commands {
+            let args: Vec<String> = command.iter().map(|arg| arg.render(event)).collect();
+            debug!("running {args:?}");
+            let mut child = Command::new(&args[0])
+                .args(&args[1..])
+                .stdin(Stdio::null())
+                .spawn()
+                .map_err(|err| format!("failed to run {}: {err}", args[0]))?;
+            // Reaped in the background so a slow command doesn't hold up the others
+            thread::spawn(move || match child.wait() {
+                Ok(status) if !status.success() => warn!("{} exited with {status}", args[0]),
+                Ok(_) => (),
+                Err(err) => warn!("failed to wait for {}: {err}", args[0]),
+            });
+        }
+        Ok(())
+    }
+
+    fn validate(&self, conf: &Config) -> Result<(), Box<dyn Error>> {
+        ExecSink::new(conf)?;
+        Ok(())
+    }
+
+    fn configure(&mut self, conf: &Config) -> Result<(), Box<dyn Error>> {
+        *self = ExecSink::new(conf)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,231 @@
+use crate::{
+    change::Change,
+    config::{Backend, Config},
+    device::{self, Device, DeviceClass, DeviceFilter},
+    error::BlightError,
+    uevent::UeventWatcher,
+};
+use log::{debug, error, info, warn};
+use notify::{Config as NotifyConfig, Event, PollWatcher, RecursiveMode, Watcher};
+use std::{
+    error::Error,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+/// Origin of brightness changes, reported through the handler it was created with
+pub trait BrightnessSource {
+    /// Devices currently watched
+    fn devices(&self) -> &[Device];
+
+    /// Looks for devices again, watching the ones that showed up and dropping the
+    /// ones that disappeared. Returns the devices that showed up
+    fn rescan(&mut self) -> Result<Vec<Device>, BlightError> {
+        Ok(Vec::new())
+    }
+
+    /// Applies a reloaded configuration, leaving the source untouched on error
+    fn configure(&mut self, _conf: &Config) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+/// Devices of `/sys/class/backlight` and `/sys/class/leds`, watched with the
+/// configured backend
+pub struct SysfsSource {
+    watcher: Box<dyn Watcher>,
+    devices: Vec<Device>,
+    sysfs_root: PathBuf,
+    classes: &'static [DeviceClass],
+    filter: DeviceFilter,
+    actual: bool,
+}
+
+impl SysfsSource {
+    /// Discovers and watches the devices, failing if there is none to watch
+    pub fn new<F>(conf: &Config, on_change: F) -> Result<Self, Box<dyn Error>>
+    where
+        F: Fn(Change) + Clone + Send + 'static,
+    {
+        let mut watcher = init_watcher(conf, on_change)?;
+        let filter = conf.device_filter()?;
+        let devices = filter.select(device::discover(&conf.sysfs_root, conf.classes())?);
+        if devices.is_empty() {
+            return Err(BlightError::NoDevices(conf.sysfs_root.join("class/backlight")).into());
+        }
+        watch(watcher.as_mut(), &devices, conf.actual)?;
+        Ok(SysfsSource {
+            watcher,
+            devices,
+            sysfs_root: conf.sysfs_root.clone(),
+            classes: conf.classes(),
+            filter,
+            actual: conf.actual,
+        })
+    }
+}
+
+impl BrightnessSource for SysfsSource {
+    fn devices(&self) -> &[Device] {
+        &self.devices
+    }
+
+    fn rescan(&mut self) -> Result<Vec<Device>, BlightError> {
+        let found = self
+            .filter
+            .select(device::discover(&self.sysfs_root, self.classes)?);
+        Ok(rewatch(
+            self.watcher.as_mut(),
+            &mut self.devices,
+            found,
+            self.actual,
+        ))
+    }
+
+    /// The backend, poll rate and actual brightness setting are kept, as the
+    /// watcher was created with them
+    fn configure(&mut self, conf: &Config) -> Result<(), Box<dyn Error>> {
+        self.filter = conf.device_filter()?;
+        self.sysfs_root = conf.sysfs_root.clone();
+        self.classes = conf.classes();
+        Ok(())
+    }
+}
+
+/// Source fed by hand, for embedding and testing without sysfs
+pub struct FakeSource<F> {
+    devices: Vec<Device>,
+    on_change: F,
+}
+
+impl<F: Fn(Change)> FakeSource<F> {
+    pub fn new(devices: Vec<Device>, on_change: F) -> Self {
+        FakeSource { devices, on_change }
+    }
+
+    /// Reports a new brightness for one of the devices
+    pub fn set(&self, device: &Device, raw: u64, max: u64) {
+        (self.on_change)(Change {
+            class: device.class,
+            device: device.name.clone(),
+            path: device.path.clone(),
+            raw,
+            max,
+            timestamp: SystemTime::now(),
+        });
+    }
+}
+
+impl<F> BrightnessSource for FakeSource<F> {
+    fn devices(&self) -> &[Device] {
+        &self.devices
+    }
+}
+
+fn watch(watcher: &mut dyn Watcher, devices: &[Device], actual: bool) -> notify::Result<()> {
+    for d in devices {
+        for p in d.watch_paths(actual) {
+            watcher.watch(&p, RecursiveMode::NonRecursive)?;
+            info!("watching {}: {} ({})", d.class, p.display(), d.kind);
+        }
+    }
+    Ok(())
+}
+
+/// Brings the watched devices in line with a fresh scan, dropping the ones that
+/// disappeared and watching the ones that showed up, which are returned
+fn rewatch(
+    watcher: &mut dyn Watcher,
+    watched: &mut Vec<Device>,
+    found: Vec<Device>,
+    actual: bool,
+) -> Vec<Device> {
+    watched.retain(|d| {
+        if found.iter().any(|f| f.path == d.path) {
+            return true;
+        }
+        info!("{} device removed: {}", d.class, d.name);
+        // The attributes are gone along with the device, so unwatch whatever may have been watched
+        for attr in ["brightness", "actual_brightness"] {
+            if let Err(err) = watcher.unwatch(&d.path.join(attr)) {
+                debug!("failed to unwatch {} {attr}: {err}", d.name);
+            }
+        }
+        false
+    });
+    let mut added = Vec::new();
+    for d in found {
+        if watched.iter().any(|w| w.path == d.path) {
+            continue;
+        }
+        info!("{} device added: {}", d.class, d.name);
+        match watch(watcher, std::slice::from_ref(&d), actual) {
+            Ok(()) => {
+                watched.push(d.clone());
+                added.push(d);
+            }
+            Err(err) => error!("failed to watch {}: {err}", d.name),
+        }
+    }
+    added
+}
+
+fn init_watcher<F>(conf: &Config, on_change: F) -> notify::Result<Box<dyn Watcher>>
+where
+    F: Fn(Change) + Clone + Send + 'static,
+{
+    let (poll_rate, backend, actual) = (conf.pollrate, conf.backend, conf.actual);
+    let event_handler = || {
+        let on_change = on_change.clone();
+        move |ev| handler(ev, &on_change, actual)
+    };
+    let config = NotifyConfig::default()
+        .with_compare_contents(true)
+        .with_poll_interval(Duration::from_secs_f32(poll_rate));
+    let watcher: Box<dyn Watcher> = match backend {
+        Backend::Poll => Box::new(PollWatcher::new(event_handler(), config)?),
+        Backend::Uevent => Box::new(UeventWatcher::new(event_handler(), config)?),
+        Backend::Auto => match UeventWatcher::with_poll_fallback(event_handler(), config) {
+            Ok(w) => Box::new(w),
+            Err(err) => {
+                warn!("uevent backend unavailable, falling back to polling: {err}");
+                Box::new(PollWatcher::new(event_handler(), config)?)
+            }
+        },
+    };
+    info!("using {backend:?} backend");
+    Ok(watcher)
+}
+
+fn handler(ev: notify::Result<Event>, on_change: &impl Fn(Change), actual: bool) {
+    match read_change(ev, actual) {
+        Ok(Some(change)) => on_change(change),
+        Ok(None) => (),
+        Err(err) => error!("{err}"),
+    }
+}
+
+fn read_change(ev: notify::Result<Event>, actual: bool) -> Result<Option<Change>, BlightError> {
+    let mut event = ev?;
+    // Removed devices are picked up by the rescan, only content changes matter here
+    if !event.kind.is_modify() {
+        debug!("ignoring {:?} event for {:?}", event.kind, event.paths);
+        return Ok(None);
+    }
+    let p = event.paths.pop().ok_or(BlightError::EventPath)?;
+    let class = DeviceClass::of_path(&p).unwrap_or(DeviceClass::Backlight);
+    let dir = p.parent().ok_or(BlightError::EventPath)?;
+    let device = dir
+        .file_name()
+        .ok_or(BlightError::EventPath)?
+        .to_string_lossy()
+        .into_owned();
+    Ok(Some(Change {
+        class,
+        device,
+        path: dir.to_path_buf(),
+        raw: device::read_value(&device::brightness_file(dir, actual))?,
+        max: device::read_value(&dir.join("max_brightness"))?,
+        timestamp: SystemTime::now(),
+    }))
+}
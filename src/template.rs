@@ -0,0 +1,190 @@
+use crate::event::BrightnessEvent;
+
+const BAR_WIDTH: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Var {
+    Percent,
+    Raw,
+    Max,
+    Device,
+    Delta,
+    Direction,
+    Bar,
+}
+
+impl Var {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "percent" => Var::Percent,
+            "raw" => Var::Raw,
+            "max" => Var::Max,
+            "device" => Var::Device,
+            "delta" => Var::Delta,
+            "direction" => Var::Direction,
+            "bar" => Var::Bar,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Text(String),
+    Var(Var),
+}
+
+/// Notification text with `{percent}`, `{raw}`, `{max}`, `{device}`, `{delta}`,
+/// `{direction}` and `{bar}` placeholders, `{{` and `}}` escape literal braces
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template(Vec<Segment>);
+
+impl Template {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("unclosed '{{' in '{s}'")),
+                        }
+                    }
+                    let var = Var::from_name(&name)
+                        .ok_or_else(|| format!("unknown placeholder '{{{name}}}' in '{s}'"))?;
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    segments.push(Segment::Var(var));
+                }
+                '}' => return Err(format!("unmatched '}}' in '{s}'")),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+        Ok(Template(segments))
+    }
+
+    /// Appends ` {percent}%` to templates without any placeholder, so plain
+    /// messages keep working like they used to
+    pub fn with_default_percent(mut self) -> Self {
+        if !self.0.iter().any(|s| matches!(s, Segment::Var(_))) {
+            self.0.push(Segment::Text(" ".into()));
+            self.0.push(Segment::Var(Var::Percent));
+            self.0.push(Segment::Text("%".into()));
+        }
+        self
+    }
+
+    pub fn render(&self, event: &BrightnessEvent) -> String {
+        let mut out = String::new();
+        for segment in &self.0 {
+            match segment {
+                Segment::Text(text) => out.push_str(text),
+                Segment::Var(var) => out.push_str(&render_var(*var, event)),
+            }
+        }
+        out
+    }
+}
+
+fn render_var(var: Var, event: &BrightnessEvent) -> String {
+    match var {
+        Var::Percent => event.percent.to_string(),
+        Var::Raw => event.raw.to_string(),
+        Var::Max => event.max.to_string(),
+        Var::Device => event.device.clone(),
+        Var::Delta => format!("{:+}", event.delta),
+        Var::Direction => event.direction.symbol().to_owned(),
+        Var::Bar => {
+            let filled = (event.percent as usize * BAR_WIDTH + 50) / 100;
+            let filled = filled.min(BAR_WIDTH);
+            "█".repeat(filled) + &"░".repeat(BAR_WIDTH - filled)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{change::Direction, device::DeviceClass};
+    use std::time::SystemTime;
+
+    fn event() -> BrightnessEvent {
+        BrightnessEvent {
+            class: DeviceClass::Backlight,
+            device: "panel".into(),
+            path: "/sys/class/backlight/panel".into(),
+            raw: 420,
+            max: 1000,
+            percent: 42,
+            previous: Some(320),
+            delta: 10,
+            direction: Direction::Up,
+            timestamp: SystemTime::now(),
+        }
+    }
+
+    fn render(s: &str) -> String {
+        Template::parse(s).unwrap().render(&event())
+    }
+
+    #[test]
+    fn placeholders() {
+        assert_eq!(render("{device}: {percent}%"), "panel: 42%");
+        assert_eq!(render("{raw}/{max} {delta}{direction}"), "420/1000 +10▲");
+        assert_eq!(render("{bar}"), "████░░░░░░");
+    }
+
+    #[test]
+    fn escaped_braces() {
+        assert_eq!(render("{{percent}} {{{percent}}}"), "{percent} {42}");
+        assert_eq!(render("}}"), "}");
+    }
+
+    #[test]
+    fn unknown_placeholder() {
+        assert_eq!(
+            Template::parse("{level}"),
+            Err("unknown placeholder '{level}' in '{level}'".into())
+        );
+    }
+
+    #[test]
+    fn unclosed_brace() {
+        assert_eq!(
+            Template::parse("{percent"),
+            Err("unclosed '{' in '{percent'".into())
+        );
+        assert_eq!(Template::parse("50}"), Err("unmatched '}' in '50}'".into()));
+    }
+
+    #[test]
+    fn default_percent() {
+        let plain = Template::parse("Brightness").unwrap();
+        assert_eq!(
+            plain.with_default_percent().render(&event()),
+            "Brightness 42%"
+        );
+        let custom = Template::parse("{raw}").unwrap();
+        assert_eq!(custom.with_default_percent().render(&event()), "420");
+        // Escaped braces aren't placeholders
+        let escaped = Template::parse("{{x}}").unwrap();
+        assert_eq!(escaped.with_default_percent().render(&event()), "{x} 42%");
+    }
+}
@@ -0,0 +1,141 @@
+use crate::{change::Change, device::DeviceClass, scale::PercentMapping};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, str::FromStr};
+
+/// Smallest change worth a notification, either in percent (`2%`) or in raw steps (`10`)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum MinDelta {
+    Percent(f64),
+    Raw(u64),
+}
+
+impl FromStr for MinDelta {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s.strip_suffix('%') {
+            Some(percent) => percent
+                .trim()
+                .parse()
+                .ok()
+                .filter(|p: &f64| (0. ..=100.).contains(p))
+                .map(MinDelta::Percent)
+                .ok_or_else(|| format!("invalid percentage '{s}'")),
+            None => s
+                .parse()
+                .map(MinDelta::Raw)
+                .map_err(|_| format!("invalid raw step count '{s}', use a trailing % for percent")),
+        }
+    }
+}
+
+impl TryFrom<String> for MinDelta {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for MinDelta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MinDelta::Percent(p) => write!(f, "{p}%"),
+            MinDelta::Raw(steps) => write!(f, "{steps}"),
+        }
+    }
+}
+
+impl From<MinDelta> for String {
+    fn from(delta: MinDelta) -> Self {
+        delta.to_string()
+    }
+}
+
+/// Drops changes too small to be worth a notification, compared to the last value
+/// accepted for the same device
+#[derive(Debug)]
+pub struct Threshold {
+    min_delta: Option<MinDelta>,
+    /// Only notify when the displayed integer percentage changes
+    percent_changes: bool,
+    mapping: PercentMapping,
+    last: HashMap<(DeviceClass, String), Change>,
+}
+
+impl Threshold {
+    pub fn new(
+        min_delta: Option<MinDelta>,
+        percent_changes: bool,
+        mapping: PercentMapping,
+    ) -> Self {
+        Threshold {
+            min_delta,
+            percent_changes,
+            mapping,
+            last: HashMap::new(),
+        }
+    }
+
+    pub fn configure(
+        &mut self,
+        min_delta: Option<MinDelta>,
+        percent_changes: bool,
+        mapping: PercentMapping,
+    ) {
+        self.min_delta = min_delta;
+        self.percent_changes = percent_changes;
+        self.mapping = mapping;
+    }
+
+    /// Tells whether the change is worth a notification. Changes are compared to the
+    /// last accepted one, so small steps add up until they are worth one
+    pub fn accept(&mut self, change: &Change) -> bool {
+        let key = (change.class, change.device.clone());
+        let accepted = match self.last.get(&key) {
+            Some(last) => self.differs(last, change),
+            None => true,
+        };
+        if accepted {
+            self.last.insert(key, change.clone());
+        }
+        accepted
+    }
+
+    fn differs(&self, last: &Change, change: &Change) -> bool {
+        let mapping = &self.mapping;
+        if self.percent_changes && mapping.percent(last) == mapping.percent(change) {
+            return false;
+        }
+        match self.min_delta {
+            // With some slack, so a step of exactly the minimum isn't lost to rounding errors
+            Some(MinDelta::Percent(p)) => {
+                (mapping.ratio(change) - mapping.ratio(last)).abs() * 100. >= p - 1e-9
+            }
+            Some(MinDelta::Raw(steps)) => change.raw.abs_diff(last.raw) >= steps,
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scale::{Rounding, Scale};
+
+    #[test]
+    fn small_steps_add_up() {
+        let mapping = PercentMapping {
+            scale: Scale::Linear,
+            exponent: 2.,
+            rounding: Rounding::Round,
+        };
+        let mut threshold = Threshold::new(Some(MinDelta::Percent(5.)), false, mapping);
+        let accepted: Vec<u64> = (50..=60)
+            .filter(|raw| threshold.accept(&Change::panel(*raw)))
+            .collect();
+        assert_eq!(accepted, [50, 55, 60]);
+    }
+}
@@ -0,0 +1,428 @@
+//! Kernel uevent based change detection
+//!
+//! The backlight class emits a `change` uevent whenever the brightness goes through
+//! it, for writes to the `brightness` attribute (`SOURCE=sysfs`) as well as changes
+//! the driver reports on behalf of the firmware (`SOURCE=hotkey`, e.g. hotkeys
+//! handled by the EC). These are received over a netlink socket without any polling.
+//! Some drivers let the firmware change the brightness without reporting it, so the
+//! watcher can fall back to polling backlights until they have proven to emit
+//! uevents. The leds class never emits one for brightness changes, so leds are
+//! always polled, as is `actual_brightness`, which changes without a uevent.
+
+use log::{debug, error, info, warn};
+use notify::{
+    event::{DataChange, ModifyKind},
+    Config, Event, EventHandler, EventKind, PollWatcher, RecursiveMode, Watcher, WatcherKind,
+};
+use std::{
+    collections::HashMap,
+    fs, io, mem,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+/// Watched attribute paths keyed by (subsystem, device name)
+type Watches = HashMap<(String, String), Vec<PathBuf>>;
+
+/// Last known content of the watched attributes, to tell which devices changed
+/// while uevents were lost
+type Contents = HashMap<PathBuf, String>;
+
+pub struct UeventWatcher {
+    watches: Arc<Mutex<Watches>>,
+    contents: Arc<Mutex<Contents>>,
+    /// Polls the attributes uevents don't cover, and with a fallback the backlights
+    /// that haven't emitted a uevent yet
+    poller: Arc<Mutex<PollWatcher>>,
+    fallback: bool,
+    stop: Arc<AtomicBool>,
+}
+
+impl UeventWatcher {
+    /// Creates a watcher that also polls every watched backlight until a uevent is
+    /// received for it
+    pub fn with_poll_fallback<F: EventHandler>(
+        event_handler: F,
+        config: Config,
+    ) -> notify::Result<Self> {
+        Self::spawn(event_handler, config, true)
+    }
+
+    fn spawn<F: EventHandler>(
+        event_handler: F,
+        config: Config,
+        fallback: bool,
+    ) -> notify::Result<Self> {
+        let handler = Arc::new(Mutex::new(event_handler));
+        let contents = Arc::new(Mutex::new(Contents::new()));
+        let (poll_handler, poll_contents) = (Arc::clone(&handler), Arc::clone(&contents));
+        let poller = PollWatcher::new(
+            move |ev: notify::Result<Event>| {
+                if let Ok(ev) = &ev {
+                    update_contents(&mut poll_contents.lock().unwrap(), &ev.paths);
+                }
+                poll_handler.lock().unwrap().handle_event(ev)
+            },
+            config,
+        )?;
+        let poller = Arc::new(Mutex::new(poller));
+        let socket = open_socket().map_err(notify::Error::io)?;
+        let watches = Arc::new(Mutex::new(Watches::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let listener = Listener {
+            socket,
+            watches: Arc::clone(&watches),
+            contents: Arc::clone(&contents),
+            poller: Arc::clone(&poller),
+            fallback,
+            stop: Arc::clone(&stop),
+        };
+        thread::Builder::new()
+            .name("uevent listener".into())
+            .spawn(move || listener.run(handler))
+            .map_err(notify::Error::io)?;
+        Ok(UeventWatcher {
+            watches,
+            contents,
+            poller,
+            fallback,
+            stop,
+        })
+    }
+}
+
+impl Watcher for UeventWatcher {
+    /// Creates a watcher polling only what uevents don't cover
+    fn new<F: EventHandler>(event_handler: F, config: Config) -> notify::Result<Self> {
+        Self::spawn(event_handler, config, false)
+    }
+
+    fn watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> notify::Result<()> {
+        let key = device_key(path).ok_or_else(|| {
+            notify::Error::generic("not a sysfs class device attribute").add_path(path.into())
+        })?;
+        if self.fallback || always_polled(path) {
+            self.poller.lock().unwrap().watch(path, recursive_mode)?;
+        }
+        let mut watches = self.watches.lock().unwrap();
+        let paths = watches.entry(key).or_default();
+        if !paths.iter().any(|p| p == path) {
+            paths.push(path.into());
+        }
+        if let Ok(content) = fs::read_to_string(path) {
+            self.contents.lock().unwrap().insert(path.into(), content);
+        }
+        Ok(())
+    }
+
+    fn unwatch(&mut self, path: &Path) -> notify::Result<()> {
+        let key = device_key(path).ok_or_else(notify::Error::watch_not_found)?;
+        // The device may have already stopped being polled, or never was
+        let _ = self.poller.lock().unwrap().unwatch(path);
+        let mut watches = self.watches.lock().unwrap();
+        let paths = watches
+            .get_mut(&key)
+            .filter(|paths| paths.iter().any(|p| p == path))
+            .ok_or_else(|| notify::Error::watch_not_found().add_path(path.into()))?;
+        paths.retain(|p| p != path);
+        if paths.is_empty() {
+            watches.remove(&key);
+        }
+        self.contents.lock().unwrap().remove(path);
+        Ok(())
+    }
+
+    fn kind() -> WatcherKind {
+        WatcherKind::NullWatcher
+    }
+}
+
+impl Drop for UeventWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+struct Listener {
+    socket: OwnedFd,
+    watches: Arc<Mutex<Watches>>,
+    contents: Arc<Mutex<Contents>>,
+    poller: Arc<Mutex<PollWatcher>>,
+    fallback: bool,
+    stop: Arc<AtomicBool>,
+}
+
+impl Listener {
+    fn run<F: EventHandler>(self, handler: Arc<Mutex<F>>) {
+        let mut buf = [0u8; 8192];
+        while !self.stop.load(Ordering::Relaxed) {
+            let len = match recv(&self.socket, &mut buf) {
+                Ok(len) => len,
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+                    ) =>
+                {
+                    continue
+                }
+                // The socket buffer overflowed during a uevent storm, e.g. when docking
+                Err(err) if err.raw_os_error() == Some(libc::ENOBUFS) => {
+                    warn!("uevents were lost, reading every watched device again");
+                    let watches: Vec<_> = self.watches.lock().unwrap().values().cloned().collect();
+                    let mut handler = handler.lock().unwrap();
+                    for paths in watches {
+                        if update_contents(&mut self.contents.lock().unwrap(), &paths) {
+                            handler.handle_event(Ok(modified(paths)));
+                        }
+                    }
+                    continue;
+                }
+                Err(err) => {
+                    error!("uevent socket error: {err}");
+                    self.restore_polling();
+                    handler
+                        .lock()
+                        .unwrap()
+                        .handle_event(Err(notify::Error::io(err)));
+                    return;
+                }
+            };
+            let Some(uevent) = Uevent::parse(&buf[..len]) else {
+                continue;
+            };
+            if uevent.action != "change" {
+                continue;
+            }
+            let key = (uevent.subsystem.to_owned(), uevent.name().to_owned());
+            let Some(paths) = self.watches.lock().unwrap().get(&key).cloned() else {
+                continue;
+            };
+            debug!("uevent received for {}", key.1);
+            if self.fallback && key.0 == "backlight" {
+                // Uevents stand for `brightness` only, firmware changes to
+                // `actual_brightness` are still only seen by polling
+                let brightness = paths.iter().find(|p| p.ends_with("brightness"));
+                if brightness.is_some_and(|p| self.poller.lock().unwrap().unwatch(p).is_ok()) {
+                    info!("{} emits uevents, no longer polling its brightness", key.1);
+                }
+            }
+            update_contents(&mut self.contents.lock().unwrap(), &paths);
+            handler.lock().unwrap().handle_event(Ok(modified(paths)));
+        }
+    }
+
+    /// Polls every watched attribute again, as no more uevents will be received
+    fn restore_polling(&self) {
+        let mut poller = self.poller.lock().unwrap();
+        for path in self.watches.lock().unwrap().values().flatten() {
+            if let Err(err) = poller.watch(path, RecursiveMode::NonRecursive) {
+                error!("failed to poll {}: {err}", path.display());
+            }
+        }
+        info!("polling every watched device again");
+    }
+}
+
+/// Reads the attributes again, telling whether any of them changed
+fn update_contents(contents: &mut Contents, paths: &[PathBuf]) -> bool {
+    let mut changed = false;
+    for path in paths {
+        let Ok(content) = fs::read_to_string(path) else {
+            continue;
+        };
+        if contents.get(path) != Some(&content) {
+            contents.insert(path.clone(), content);
+            changed = true;
+        }
+    }
+    changed
+}
+
+fn modified(paths: Vec<PathBuf>) -> Event {
+    let mut event = Event::new(EventKind::Modify(ModifyKind::Data(DataChange::Content)));
+    event.paths = paths;
+    event
+}
+
+struct Uevent<'a> {
+    action: &'a str,
+    devpath: &'a str,
+    subsystem: &'a str,
+}
+
+impl<'a> Uevent<'a> {
+    /// Parses a kernel uevent message, `action@devpath` followed by NUL separated
+    /// `KEY=value` pairs
+    fn parse(msg: &'a [u8]) -> Option<Self> {
+        let msg = std::str::from_utf8(msg).ok()?;
+        let mut fields = msg.split('\0');
+        fields.next()?.split_once('@')?;
+        let (mut action, mut devpath, mut subsystem) = (None, None, None);
+        for (key, value) in fields.filter_map(|f| f.split_once('=')) {
+            match key {
+                "ACTION" => action = Some(value),
+                "DEVPATH" => devpath = Some(value),
+                "SUBSYSTEM" => subsystem = Some(value),
+                _ => (),
+            }
+        }
+        Some(Uevent {
+            action: action?,
+            devpath: devpath?,
+            subsystem: subsystem?,
+        })
+    }
+
+    fn name(&self) -> &'a str {
+        self.devpath.rsplit('/').next().unwrap_or(self.devpath)
+    }
+}
+
+/// Attributes whose changes come without a uevent: those of leds and `actual_brightness`
+fn always_polled(path: &Path) -> bool {
+    path.ends_with("actual_brightness")
+        || device_key(path).is_some_and(|(subsystem, _)| subsystem == "leds")
+}
+
+/// Extracts (subsystem, device name) from a `<root>/class/<subsystem>/<device>/<attribute>` path
+fn device_key(path: &Path) -> Option<(String, String)> {
+    let device = path.parent()?;
+    let subsystem = device.parent()?.file_name()?;
+    Some((
+        subsystem.to_string_lossy().into_owned(),
+        device.file_name()?.to_string_lossy().into_owned(),
+    ))
+}
+
+fn open_socket() -> io::Result<OwnedFd> {
+    // SAFETY: plain libc calls, the descriptor is owned as soon as it is created
+    unsafe {
+        let fd = libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+            libc::NETLINK_KOBJECT_UEVENT,
+        );
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = OwnedFd::from_raw_fd(fd);
+        let mut addr: libc::sockaddr_nl = mem::zeroed();
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        // Kernel uevent multicast group
+        addr.nl_groups = 1;
+        let res = libc::bind(
+            fd,
+            &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        );
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Wake up periodically so the listener notices when the watcher is dropped
+        let timeout = libc::timeval {
+            tv_sec: 1,
+            tv_usec: 0,
+        };
+        let res = libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_RCVTIMEO,
+            &timeout as *const libc::timeval as *const libc::c_void,
+            mem::size_of::<libc::timeval>() as libc::socklen_t,
+        );
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(socket)
+    }
+}
+
+fn recv(socket: &OwnedFd, buf: &mut [u8]) -> io::Result<usize> {
+    // SAFETY: the buffer is valid for writes of its length
+    let len = unsafe {
+        libc::recv(
+            socket.as_raw_fd(),
+            buf.as_mut_ptr() as *mut libc::c_void,
+            buf.len(),
+            0,
+        )
+    };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(len as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHANGE: &[u8] =
+        b"change@/devices/pci0000:00/0000:00:02.0/drm/card1/card1-eDP-1/intel_backlight\0\
+        ACTION=change\0\
+        DEVPATH=/devices/pci0000:00/0000:00:02.0/drm/card1/card1-eDP-1/intel_backlight\0\
+        SUBSYSTEM=backlight\0\
+        SOURCE=hotkey\0\
+        SEQNUM=4711\0";
+
+    #[test]
+    fn parses_kernel_change() {
+        let uevent = Uevent::parse(CHANGE).unwrap();
+        assert_eq!(uevent.action, "change");
+        assert_eq!(uevent.subsystem, "backlight");
+        assert_eq!(uevent.name(), "intel_backlight");
+    }
+
+    #[test]
+    fn rejects_missing_fields() {
+        assert!(Uevent::parse(b"change@/devices/x\0ACTION=change\0DEVPATH=/devices/x\0").is_none());
+        assert!(Uevent::parse(b"ACTION=change\0DEVPATH=/x\0SUBSYSTEM=backlight\0").is_none());
+        assert!(Uevent::parse(b"").is_none());
+    }
+
+    #[test]
+    fn rejects_libudev_messages() {
+        let mut msg = b"libudev\0".to_vec();
+        // Magic and header of the udev monitor protocol, then the same properties
+        msg.extend([0xfe, 0xed, 0xca, 0xfe, 40, 0, 0, 0]);
+        msg.extend(&CHANGE[CHANGE.iter().position(|b| *b == 0).unwrap() + 1..]);
+        assert!(Uevent::parse(&msg).is_none());
+        let mut text = b"libudev\0".to_vec();
+        text.extend(&CHANGE[CHANGE.iter().position(|b| *b == 0).unwrap() + 1..]);
+        assert!(Uevent::parse(&text).is_none());
+    }
+
+    #[test]
+    fn device_keys() {
+        assert_eq!(
+            device_key(Path::new("/sys/class/backlight/intel_backlight/brightness")),
+            Some(("backlight".into(), "intel_backlight".into()))
+        );
+        assert_eq!(
+            device_key(Path::new(
+                "/sys/class/leds/tpacpi::kbd_backlight/brightness"
+            )),
+            Some(("leds".into(), "tpacpi::kbd_backlight".into()))
+        );
+        assert_eq!(device_key(Path::new("brightness")), None);
+    }
+
+    #[test]
+    fn polled_attributes() {
+        assert!(always_polled(Path::new(
+            "/sys/class/backlight/acpi_video0/actual_brightness"
+        )));
+        assert!(always_polled(Path::new(
+            "/sys/class/leds/input3::capslock/brightness"
+        )));
+        assert!(!always_polled(Path::new(
+            "/sys/class/backlight/acpi_video0/brightness"
+        )));
+    }
+}
@@ -0,0 +1,80 @@
+use blight_notify::{
+    config::Config,
+    daemon::Message,
+    device::{Device, DeviceClass, DeviceType},
+    source::FakeSource,
+    BrightnessEvent, Daemon, Sink,
+};
+use std::{cell::RefCell, error::Error, rc::Rc};
+
+/// Keeps the events it gets, as `(device, percent)`
+struct Recorder {
+    all_changes: bool,
+    events: Rc<RefCell<Vec<(String, u8)>>>,
+}
+
+impl Sink for Recorder {
+    fn send(&mut self, event: &BrightnessEvent) -> Result<(), Box<dyn Error>> {
+        self.events
+            .borrow_mut()
+            .push((event.device.clone(), event.percent));
+        Ok(())
+    }
+
+    fn wants_all_changes(&self) -> bool {
+        self.all_changes
+    }
+}
+
+fn device(name: &str, class: DeviceClass) -> Device {
+    Device {
+        name: name.into(),
+        path: format!("/sys/class/{}/{name}", class.dir()).into(),
+        class,
+        kind: DeviceType::Raw,
+    }
+}
+
+#[test]
+fn changes_flow_from_source_to_sinks() {
+    let conf = Config {
+        // Long enough for the whole test to be one burst per device
+        debounce: 10_000,
+        ..Config::default()
+    };
+    let mut daemon = Daemon::new(conf, || Ok(Config::default()));
+    let filtered = Rc::new(RefCell::new(Vec::new()));
+    let all = Rc::new(RefCell::new(Vec::new()));
+    daemon.add_sink(Box::new(Recorder {
+        all_changes: false,
+        events: Rc::clone(&filtered),
+    }));
+    daemon.add_sink(Box::new(Recorder {
+        all_changes: true,
+        events: Rc::clone(&all),
+    }));
+    let panel = device("panel", DeviceClass::Backlight);
+    let keyboard = device("kbd_backlight", DeviceClass::Keyboard);
+    let source = FakeSource::new(
+        vec![panel.clone(), keyboard.clone()],
+        daemon.change_handler(),
+    );
+    source.set(&panel, 30, 100);
+    source.set(&keyboard, 2, 3);
+    // Debounced, as it comes right after the first change of the panel
+    source.set(&panel, 40, 100);
+    daemon.sender().send(Message::Shutdown).unwrap();
+    daemon.run(source).unwrap();
+    assert_eq!(
+        *filtered.borrow(),
+        [("panel".into(), 30), ("kbd_backlight".into(), 66)]
+    );
+    assert_eq!(
+        *all.borrow(),
+        [
+            ("panel".into(), 30),
+            ("kbd_backlight".into(), 66),
+            ("panel".into(), 40)
+        ]
+    );
+}